@@ -0,0 +1,240 @@
+use async_std::prelude::*;
+use async_std::sync::Barrier;
+use async_std::{future, io, net, stream, task};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chat_program_study::utils::{self, ChatResult};
+use chat_program_study::{Client, Server};
+
+// the load generator opens a number of simulated clients against a running server, every client joins the same
+// set of rooms and keeps posting to them at a fixed rate. every posted message carries the sender's wall clock time
+// so whoever receives it can work out the end-to-end delivery latency. lagged receivers are reported by the server
+// as "Dropped N message from ROOM." errors, and anything we never see at all is counted as missing.
+
+const SETTLE_TIME: Duration = Duration::from_millis(500); //give the server time to subscribe everyone before posting
+const DRAIN_TIME: Duration = Duration::from_secs(2); //how long we keep listening after the last post
+
+struct LoadSettings {
+    addr: String,
+    clients: usize,
+    rooms: usize,
+    rate: f64, //posts per second, per client
+    duration: Duration,
+}
+
+#[derive(Default)]
+struct Stats {
+    joined: u64, //clients that got connected and joined their rooms, the others never see a post
+    sent: u64,
+    received: u64,
+    dropped: u64, //reported by the server through RecvError::Lagged
+    foreign: u64, //messages that weren't produced by this run
+    errors: u64,
+    latencies_us: Vec<u64>,
+}
+
+fn arg_or<T>(n: usize, default: T) -> ChatResult<T>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match std::env::args().nth(n) {
+        Some(value) => Ok(value.parse()?),
+        None => Ok(default),
+    }
+}
+
+fn parse_settings() -> ChatResult<LoadSettings> {
+    let addr = std::env::args()
+        .nth(1)
+        .ok_or("Usage: chat_loadgen ADDRESS:PORT [CLIENTS] [ROOMS] [RATE] [SECONDS]")?;
+    let settings = LoadSettings {
+        addr,
+        clients: arg_or(2, 10)?,
+        rooms: arg_or(3, 2)?,
+        rate: arg_or(4, 10.0)?,
+        duration: Duration::from_secs(arg_or(5, 10)?),
+    };
+
+    if settings.clients == 0 || settings.rooms == 0 || settings.duration.is_zero() {
+        return Err("CLIENTS, ROOMS and SECONDS must be at least 1".into());
+    }
+    if !settings.rate.is_finite() || settings.rate <= 0.0 {
+        return Err("RATE must be a positive number of posts per second".into());
+    }
+    Ok(settings)
+}
+
+fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+fn room_name(room: usize) -> Arc<String> {
+    Arc::new(format!("loadgen-{}", room))
+}
+
+//message body looks like "loadgen CLIENT SEQ SENT_MICROS", anything else in the room is somebody else's traffic
+fn parse_sent_time(message: &str) -> Option<u64> {
+    let mut parts = message.split(' ');
+    if parts.next()? != "loadgen" {
+        return None;
+    }
+    let _client = parts.next()?;
+    let _seq = parts.next()?;
+    parts.next()?.parse().ok()
+}
+
+//pulls N out of "Dropped N message from ROOM."
+fn parse_dropped(error: &str) -> Option<u64> {
+    error.strip_prefix("Dropped ")?.split(' ').next()?.parse().ok()
+}
+
+async fn connect_and_join(settings: &LoadSettings) -> ChatResult<net::TcpStream> {
+    let mut socket = net::TcpStream::connect(settings.addr.as_str()).await?;
+    socket.set_nodelay(true)?;
+
+    for room in 0..settings.rooms {
+        utils::send_json(&mut socket, &Client::Join { chat_name: room_name(room) }).await?;
+    }
+    socket.flush().await?;
+    Ok(socket)
+}
+
+async fn post_loop(id: usize, mut socket: net::TcpStream, settings: Arc<LoadSettings>, stats: Arc<Mutex<Stats>>) -> ChatResult<()> {
+    task::sleep(SETTLE_TIME).await;
+
+    let mut ticks = stream::interval(Duration::from_secs_f64(1.0 / settings.rate));
+    let started = std::time::Instant::now();
+    let mut seq: u64 = 0;
+
+    while started.elapsed() < settings.duration {
+        ticks.next().await;
+
+        let post = Client::Post {
+            chat_name: room_name(seq as usize % settings.rooms),
+            message: Arc::new(format!("loadgen {} {} {}", id, seq, now_micros())),
+        };
+        utils::send_json(&mut socket, &post).await?;
+        socket.flush().await?;
+
+        seq += 1;
+        stats.lock().unwrap().sent += 1;
+    }
+    Ok(())
+}
+
+async fn receive_loop(socket: net::TcpStream, stats: Arc<Mutex<Stats>>) -> ChatResult<()> {
+    let mut from_server = utils::receive(io::BufReader::new(socket));
+
+    while let Some(packet) = from_server.next().await {
+        let received_at = now_micros();
+        let mut stats = stats.lock().unwrap();
+
+        match packet? {
+            Server::Message { message, .. } => match parse_sent_time(&message) {
+                Some(sent_at) => {
+                    stats.received += 1;
+                    stats.latencies_us.push(received_at.saturating_sub(sent_at));
+                }
+                None => stats.foreign += 1,
+            },
+            Server::File { .. } | Server::DirectFile { .. } | Server::Direct { .. } | Server::Missed { .. } | Server::Found { .. } => stats.foreign += 1,
+            Server::Presence { .. } | Server::Typing { .. } | Server::Reactions { .. } | Server::FileAnswer { .. } | Server::PeerWelcome { .. } => {} //not traffic, the generator never sends these
+            Server::Error(error) => match parse_dropped(&error) {
+                Some(n) => stats.dropped += n,
+                None => stats.errors += 1,
+            },
+        }
+    }
+    Ok(())
+}
+
+async fn run_client(id: usize, settings: Arc<LoadSettings>, stats: Arc<Mutex<Stats>>, ready: Arc<Barrier>) -> ChatResult<()> {
+    let joined = connect_and_join(&settings).await;
+    ready.wait().await; //every client has asked to join (or given up) before anyone starts posting
+    let socket = joined?;
+    stats.lock().unwrap().joined += 1;
+
+    let listen_for = SETTLE_TIME + settings.duration + DRAIN_TIME;
+    let receiver = task::spawn(future::timeout(listen_for, receive_loop(socket.clone(), stats.clone())));
+
+    post_loop(id, socket, settings, stats).await?;
+
+    match receiver.await {
+        Ok(result) => result, //the server closed the connection before the drain period ended
+        Err(_) => Ok(()), //timed out, which is the normal way of finishing
+    }
+}
+
+fn percentile(sorted: &[u64], pct: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = ((pct / 100.0) * (sorted.len() - 1) as f64).round() as usize;
+    sorted[rank.min(sorted.len() - 1)]
+}
+
+fn print_report(settings: &LoadSettings, stats: &mut Stats, client_errors: usize) {
+    stats.latencies_us.sort_unstable();
+    let latencies = &stats.latencies_us;
+
+    //every client joined every room, so each post should come back to every client that joined. a client that
+    //errored later still counts, whatever it didn't get is missing. what the server said it dropped is not
+    let expected = stats.sent * stats.joined;
+    let missing = expected.saturating_sub(stats.received + stats.dropped);
+    let never_joined = settings.clients as u64 - stats.joined;
+    let seconds = settings.duration.as_secs_f64();
+    let ms = |us: u64| us as f64 / 1000.0;
+
+    println!("==== chat load report ====");
+    println!("server        : {}", settings.addr);
+    println!("clients       : {} ({} never joined, {} errored)", settings.clients, never_joined, client_errors);
+    println!("rooms         : {}", settings.rooms);
+    println!("target rate   : {:.1} posts/s per client", settings.rate);
+    println!("duration      : {:.1}s", seconds);
+    println!("posts sent    : {} ({:.1}/s)", stats.sent, stats.sent as f64 / seconds);
+    println!("delivered     : {} of {} expected ({:.1}/s)", stats.received, expected, stats.received as f64 / seconds);
+    println!("dropped (lag) : {}", stats.dropped);
+    println!("missing       : {}", missing);
+    println!("other errors  : {}", stats.errors);
+    if stats.foreign > 0 {
+        println!("foreign msgs  : {}", stats.foreign);
+    }
+    println!(
+        "latency (ms)  : p50 {:.2}  p90 {:.2}  p99 {:.2}  max {:.2}",
+        ms(percentile(latencies, 50.0)),
+        ms(percentile(latencies, 90.0)),
+        ms(percentile(latencies, 99.0)),
+        ms(latencies.last().copied().unwrap_or(0)),
+    );
+}
+
+// cargo run --release --bin chat_loadgen localhost:8080 50 4 20 30
+// (50 clients, 4 rooms, 20 posts per second per client, for 30 seconds)
+fn main() -> ChatResult<()> {
+    let settings = Arc::new(parse_settings()?);
+    let stats = Arc::new(Mutex::new(Stats::default()));
+    let ready = Arc::new(Barrier::new(settings.clients));
+
+    task::block_on(async {
+        let clients: Vec<_> = (0..settings.clients)
+            .map(|id| task::spawn(run_client(id, settings.clone(), stats.clone(), ready.clone())))
+            .collect();
+
+        let mut client_errors = 0;
+        for client in clients {
+            if let Err(error) = client.await {
+                println!("Client error: {}", error);
+                client_errors += 1;
+            }
+        }
+
+        print_report(&settings, &mut stats.lock().unwrap(), client_errors);
+        Ok(())
+    })
+}
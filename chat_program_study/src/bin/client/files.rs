@@ -0,0 +1,215 @@
+use async_std::fs::File;
+use async_std::prelude::*;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use futures::channel::oneshot;
+
+use chat_program_study::utils::{self, ChatResult, ChatStream};
+use chat_program_study::{Client, FilePacket};
+
+// send-file CHAT PATH streams a file to a room as an Offer, base64 Chunks and a Complete, dm-file USER PATH
+// does the same for one user. the chunks only go once the server has accepted the Offer.
+// files offered by other members (or sent to us) are collected in memory as their chunks arrive and checked against the
+// offered size and sha256 on Complete, after which save-file ID PATH writes them out. one that stops arriving
+// (its sender went away and the Cancel never reached us) is forgotten after STALE_AFTER.
+
+const CHUNK_SIZE: usize = 32 * 1024; //comfortably under the server's default max_chunk_size
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(30);
+const STALE_AFTER: Duration = Duration::from_secs(10 * 60);
+
+enum State {
+    Receiving,
+    Verified,
+    Failed(String),
+}
+
+struct Incoming {
+    name: Arc<String>,
+    size: u64,
+    sha256: Arc<String>,
+    data: Vec<u8>,
+    next_seq: u64,
+    state: State,
+    last_heard: Instant,
+}
+
+#[derive(Default)]
+pub struct Files {
+    incoming: Mutex<HashMap<Arc<String>, Incoming>>,
+    sent: Mutex<HashSet<Arc<String>>>, //our own files come back to us from the room, no need to keep those
+    answers: Mutex<HashMap<Arc<String>, oneshot::Sender<Option<String>>>>, //our offers waiting for Server::FileAnswer
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+fn new_id() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    format!("{:x}-{:x}", std::process::id(), nanos)
+}
+
+impl Files {
+    //`wrap` puts each packet in the request that says where it goes, Client::File or Client::DirectFile.
+    //the file is read twice, a chunk at a time, once for its size and sha256 and once to send it, so it is never
+    //all in memory at once
+    pub async fn send_file<S, F>(&self, to_server: &mut S, path: &str, wrap: F) -> ChatResult<Arc<String>>
+    where
+        S: ChatStream,
+        F: Fn(FilePacket) -> Client,
+    {
+        let cannot_read = |e: std::io::Error| format!("cannot read {}: {}", path, e);
+        let mut buffer = vec![0; CHUNK_SIZE];
+
+        let mut file = File::open(path).await.map_err(cannot_read)?;
+        let (mut size, mut hasher) = (0, Sha256::new());
+        loop {
+            match read_chunk(&mut file, &mut buffer).await.map_err(cannot_read)? {
+                0 => break,
+                read => {
+                    hasher.update(&buffer[..read]);
+                    size += read as u64;
+                }
+            }
+        }
+        let name = std::path::Path::new(path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.to_string());
+
+        let id = Arc::new(new_id());
+        self.sent.lock().unwrap().insert(id.clone());
+
+        let offer = FilePacket::Offer { id: id.clone(), name: Arc::new(name), size, sha256: Arc::new(format!("{:x}", hasher.finalize())) };
+        let (answer, answered) = oneshot::channel();
+        self.answers.lock().unwrap().insert(id.clone(), answer);
+        send_packet(to_server, wrap(offer)).await?;
+        let answered = async_std::future::timeout(ANSWER_TIMEOUT, answered).await;
+        self.answers.lock().unwrap().remove(&id);
+        match answered {
+            Ok(Ok(None)) => {}
+            Ok(Ok(Some(refused))) => return Err(format!("the server refused it: {}", refused).into()),
+            Ok(Err(_)) | Err(_) => return Err(format!("the server did not answer the offer within {:?}", ANSWER_TIMEOUT).into()),
+        }
+
+        let mut file = File::open(path).await.map_err(cannot_read)?;
+        let (mut seq, mut sent) = (0, 0);
+        loop {
+            let read = read_chunk(&mut file, &mut buffer).await.map_err(cannot_read)?;
+            if read == 0 || sent + read as u64 > size {
+                sent += read as u64;
+                break;
+            }
+            let chunk = FilePacket::Chunk { id: id.clone(), seq, data: Arc::new(BASE64.encode(&buffer[..read])) };
+            send_packet(to_server, wrap(chunk)).await?;
+            seq += 1;
+            sent += read as u64;
+        }
+
+        if sent != size {
+            let reason = Arc::new("the file changed while it was being sent".to_string());
+            send_packet(to_server, wrap(FilePacket::Cancel { id: id.clone(), reason: reason.clone() })).await?;
+            return Err(format!("{}: {}", path, reason).into());
+        }
+        send_packet(to_server, wrap(FilePacket::Complete { id: id.clone() })).await?;
+        Ok(id)
+    }
+
+    //the server's answer to one of our offers, passed on to the send_file waiting for it
+    pub fn on_answer(&self, id: &Arc<String>, refused: Option<String>) {
+        if let Some(answer) = self.answers.lock().unwrap().remove(id) {
+            let _ = answer.send(refused);
+        }
+    }
+
+    //keeps track of a file packet from a room or another user (`from` says which), returning something to tell
+    //the user when there is anything
+    pub fn on_packet(&self, from: &str, packet: FilePacket) -> Option<String> {
+        if self.sent.lock().unwrap().contains(packet.id()) {
+            return None;
+        }
+        let mut incoming = self.incoming.lock().unwrap();
+        //received files wait to be saved, the rest are dropped once nothing has been heard of them for a while
+        incoming.retain(|_, file| matches!(file.state, State::Verified) || file.last_heard.elapsed() < STALE_AFTER);
+
+        match packet {
+            FilePacket::Offer { id, name, size, sha256 } => {
+                let line = format!("File {} offered in {}: {} ({} bytes)", id, from, name, size);
+                let file = Incoming { name, size, sha256, data: Vec::new(), next_seq: 0, state: State::Receiving, last_heard: Instant::now() };
+                incoming.insert(id, file);
+                Some(line)
+            }
+            FilePacket::Chunk { id, seq, data } => {
+                let file = incoming.get_mut(&id)?;
+                if !matches!(file.state, State::Receiving) {
+                    return None;
+                }
+                file.last_heard = Instant::now();
+                //a chunk can go missing when we fall behind the room, after that the file can't be put back together
+                match BASE64.decode(data.as_bytes()) {
+                    Ok(bytes) if seq == file.next_seq && file.data.len() + bytes.len() <= file.size as usize => {
+                        file.data.extend_from_slice(&bytes);
+                        file.next_seq += 1;
+                        None
+                    }
+                    _ => {
+                        file.state = State::Failed(format!("chunk {} was missing or damaged", file.next_seq));
+                        Some(format!("File {} failed: chunk {} was missing or damaged", id, file.next_seq))
+                    }
+                }
+            }
+            FilePacket::Complete { id } => {
+                let file = incoming.get_mut(&id)?;
+                if let State::Failed(reason) = &file.state {
+                    return Some(format!("File {} ({}) finished but could not be received: {}", id, file.name, reason));
+                }
+                if file.data.len() as u64 != file.size || sha256_hex(&file.data) != *file.sha256 {
+                    file.state = State::Failed("checksum mismatch".to_string());
+                    return Some(format!("File {} ({}) failed the checksum, not saving it", id, file.name));
+                }
+                file.state = State::Verified;
+                Some(format!("File {} ({}) received, checksum ok. Save it with: save-file {} PATH", id, file.name, id))
+            }
+            FilePacket::Cancel { id, reason } => {
+                incoming.remove(&id)?;
+                Some(format!("File {} was cancelled: {}", id, reason))
+            }
+        }
+    }
+
+    pub async fn save_file(&self, id: &str, path: &str) -> ChatResult<()> {
+        let data = {
+            let incoming = self.incoming.lock().unwrap();
+            match incoming.get(&Arc::new(id.to_string())) {
+                Some(Incoming { state: State::Verified, data, .. }) => data.clone(),
+                Some(Incoming { state: State::Receiving, .. }) => return Err(format!("File {} is still arriving", id).into()),
+                Some(Incoming { state: State::Failed(reason), .. }) => return Err(format!("File {} failed: {}", id, reason).into()),
+                None => return Err(format!("No file {} has been received", id).into()),
+            }
+        };
+        async_std::fs::write(path, data).await.map_err(|e| format!("cannot write {}: {}", path, e))?;
+        Ok(())
+    }
+}
+
+//fills `buffer` as far as the file goes, so only the last chunk is short
+async fn read_chunk(file: &mut File, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match file.read(&mut buffer[filled..]).await? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    Ok(filled)
+}
+
+async fn send_packet<S: ChatStream>(to_server: &mut S, request: Client) -> ChatResult<()> {
+    utils::send_json(to_server, &request).await?;
+    to_server.flush().await?;
+    Ok(())
+}
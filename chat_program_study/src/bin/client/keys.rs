@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use chat_program_study::e2e::{self, RoomKey};
+use chat_program_study::Client;
+
+// encrypt CHAT PASSPHRASE turns on end-to-end encryption for a room (see e2e.rs in the library): from then on
+// posts and replies to CHAT are sealed before they are sent, and sealed messages arriving from CHAT are opened.
+// everyone in the room has to use the same passphrase. plain CHAT goes back to sending plaintext.
+// only message bodies are sealed: send-file is refused in an encrypted room, and since the server can't read
+// the messages there, searching them finds nothing and @mentions don't reach anyone who is away.
+
+pub struct RoomKeys {
+    keys: Mutex<HashMap<String, Arc<RoomKey>>>,
+    max_message_len: usize, //what the server takes, sealing makes a message about a third longer
+}
+
+impl RoomKeys {
+    pub fn new(max_message_len: usize) -> RoomKeys {
+        RoomKeys { keys: Mutex::default(), max_message_len }
+    }
+
+    pub fn is_encrypted(&self, chat_name: &str) -> bool {
+        self.keys.lock().unwrap().contains_key(chat_name)
+    }
+
+    pub fn set(&self, chat_name: &str, passphrase: &str) {
+        let key = RoomKey::derive(chat_name, passphrase);
+        self.keys.lock().unwrap().insert(chat_name.to_string(), Arc::new(key));
+    }
+
+    pub fn forget(&self, chat_name: &str) -> bool {
+        self.keys.lock().unwrap().remove(chat_name).is_some()
+    }
+
+    fn key(&self, chat_name: &str) -> Option<Arc<RoomKey>> {
+        self.keys.lock().unwrap().get(chat_name).cloned()
+    }
+
+    //seals the body of posts and replies going to an encrypted room, everything else goes out as it is.
+    //a message the server would turn away once sealed is refused here, where it can say why
+    pub fn seal(&self, request: Client) -> Result<Client, String> {
+        Ok(match request {
+            Client::Post { chat_name, message } => match self.key(&chat_name) {
+                Some(key) => Client::Post { message: self.sealed(&key, &chat_name, &message)?, chat_name },
+                None => Client::Post { chat_name, message },
+            },
+            Client::Reply { chat_name, reply_to, message } => match self.key(&chat_name) {
+                Some(key) => Client::Reply { message: self.sealed(&key, &chat_name, &message)?, chat_name, reply_to },
+                None => Client::Reply { chat_name, reply_to, message },
+            },
+            other => other,
+        })
+    }
+
+    fn sealed(&self, key: &RoomKey, chat_name: &str, message: &str) -> Result<Arc<String>, String> {
+        let sealed = key.seal(chat_name, message);
+        if sealed.len() > self.max_message_len {
+            return Err(format!("the message is {} bytes encrypted ({} as typed), over the server's limit of {}",
+                               sealed.len(), message.len(), self.max_message_len));
+        }
+        Ok(Arc::new(sealed))
+    }
+
+    //the text to show for a message from `chat_name`, opened when it was sealed
+    pub fn open(&self, chat_name: &str, message: Arc<String>) -> Arc<String> {
+        if !e2e::is_sealed(&message) {
+            return message;
+        }
+        let opened = match self.key(chat_name) {
+            Some(key) => key.open(chat_name, &message)
+                .unwrap_or_else(|| "[encrypted, your passphrase for this room does not open it]".to_string()),
+            None => format!("[encrypted, read it with: encrypt {} PASSPHRASE]", chat_name),
+        };
+        Arc::new(opened)
+    }
+}
@@ -0,0 +1,379 @@
+// use async_std::io::BufReader;
+use async_std::prelude::*;
+use async_std::{task, io, net};
+use clap::Parser;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chat_program_study::server::config::Limits;
+use chat_program_study::utils::{self, ChatResult, ChatStream};
+use chat_program_study::{e2e, Client, PresenceState, Server};
+
+mod files;
+mod keys;
+mod script;
+
+use files::Files;
+use keys::RoomKeys;
+
+const SEARCH_LIMIT: usize = 20;
+
+fn get_value(mut input: &str) -> Option<(&str, &str)> {
+    input = input.trim_start();
+
+    if input.is_empty() {
+        return None;
+    }
+
+    match input.find(char::is_whitespace) {
+        Some(whitespace) => Some((&input[0..whitespace], &input[whitespace..])),
+        None => Some((input, ""))
+    }
+}
+
+//turns a typed (or scripted) line into a request, None when the line isn't a valid join or post
+fn parse_input(line: &str) -> Option<Client> {
+    let (input, remainder) = get_value(line)?;
+
+    if input == "join" {
+        let (chat, remainder) = get_value(remainder)?;
+
+        if !remainder.trim_start().is_empty() {
+            return None;
+        }
+
+        Some(Client::Join {chat_name: Arc::new(chat.to_string())})
+    }
+
+    else if input == "post" {
+        let (chat, remainder) = get_value(remainder)?;
+        let message = remainder.trim_start().to_string();
+
+        Some(Client::Post{ chat_name: Arc::new(chat.to_string()), message: Arc::new(message)})
+    }
+
+    else if input == "presence" {
+        let (name, remainder) = get_value(remainder)?;
+        let state = match remainder.trim() {
+            "" | "online" => PresenceState::Online,
+            "away" => PresenceState::Away,
+            "offline" => PresenceState::Offline,
+            _ => return None,
+        };
+
+        Some(Client::Presence { name: Arc::new(name.to_string()), state })
+    }
+
+    else if input == "login" {
+        let (user_id, remainder) = get_value(remainder)?;
+
+        if !remainder.trim_start().is_empty() {
+            return None;
+        }
+
+        Some(Client::Login { user_id: Arc::new(user_id.to_string()) })
+    }
+
+    else if input == "dm" {
+        let (to, remainder) = get_value(remainder)?;
+        let message = remainder.trim_start().to_string();
+
+        Some(Client::Direct { to: Arc::new(to.to_string()), message: Arc::new(message) })
+    }
+
+    else if input == "search" {
+        let (chat, remainder) = get_value(remainder)?;
+        let query = remainder.trim();
+
+        if query.is_empty() {
+            return None;
+        }
+
+        Some(Client::Search { chat_name: Arc::new(chat.to_string()), query: Arc::new(query.to_string()), limit: SEARCH_LIMIT })
+    }
+
+    else if input == "reply" {
+        let (chat, remainder) = get_value(remainder)?;
+        let (id, remainder) = get_value(remainder)?;
+        let message = remainder.trim_start().to_string();
+
+        Some(Client::Reply { chat_name: Arc::new(chat.to_string()), reply_to: id.parse().ok()?, message: Arc::new(message) })
+    }
+
+    else if input == "react" || input == "unreact" {
+        let (chat, remainder) = get_value(remainder)?;
+        let (id, remainder) = get_value(remainder)?;
+        let (emoji, remainder) = get_value(remainder)?;
+
+        if !remainder.trim_start().is_empty() {
+            return None;
+        }
+
+        let (chat_name, message_id, emoji) = (Arc::new(chat.to_string()), id.parse().ok()?, Arc::new(emoji.to_string()));
+        if input == "react" {
+            Some(Client::React { chat_name, message_id, emoji })
+        } else {
+            Some(Client::Unreact { chat_name, message_id, emoji })
+        }
+    }
+
+    else if input == "typing" {
+        let (chat, remainder) = get_value(remainder)?;
+
+        if !remainder.trim_start().is_empty() {
+            return None;
+        }
+
+        Some(Client::Typing { chat_name: Arc::new(chat.to_string()) })
+    }
+
+    else {
+        None
+    }
+}
+
+//send-file, dm-file and save-file are carried out by the client itself, true when `line` was one of them
+async fn file_command<S: ChatStream>(line: &str, send: &mut S, files: &Files, keys: &RoomKeys) -> bool {
+    let (command, remainder) = match get_value(line) {
+        Some(found) => found,
+        None => return false,
+    };
+    let (first, path) = match get_value(remainder) {
+        Some((first, path)) if !path.trim().is_empty() => (first, path.trim()),
+        _ if ["send-file", "dm-file", "save-file"].contains(&command) => {
+            println!("Usage: send-file CHAT PATH / dm-file USER PATH / save-file ID PATH");
+            return true;
+        }
+        _ => return false,
+    };
+
+    match command {
+        "send-file" if keys.is_encrypted(first) => {
+            println!("Could not send {}: files are not encrypted, so the server would see it. Use plain {} first to send it anyway", path, first);
+        }
+        "send-file" | "dm-file" => {
+            let target = Arc::new(first.to_string());
+            let sent = match command {
+                "send-file" => files.send_file(send, path, |packet| Client::File { chat_name: target.clone(), packet }).await,
+                _ => files.send_file(send, path, |packet| Client::DirectFile { to: target.clone(), packet }).await,
+            };
+            match sent {
+                Ok(id) => println!("Sent {} to {} as file {}", path, first, id),
+                Err(error) => println!("Could not send {}: {}", path, error),
+            }
+        }
+        "save-file" => match files.save_file(first, path).await {
+            Ok(()) => println!("Saved file {} to {}", first, path),
+            Err(error) => println!("Could not save: {}", error),
+        },
+        _ => return false,
+    }
+    true
+}
+
+//encrypt and plain only change what this client does, true when `line` was one of them
+fn key_command(line: &str, keys: &RoomKeys) -> bool {
+    match get_value(line) {
+        Some(("encrypt", remainder)) => match get_value(remainder) {
+            Some((chat, passphrase)) if !passphrase.trim().is_empty() => {
+                keys.set(chat, passphrase.trim());
+                println!("Posts to {} are now end-to-end encrypted", chat);
+            }
+            _ => println!("Usage: encrypt CHAT PASSPHRASE"),
+        },
+        Some(("plain", remainder)) => match get_value(remainder) {
+            Some((chat, "")) if keys.forget(chat) => println!("Posts to {} are no longer encrypted", chat),
+            Some((chat, "")) => println!("{} was not encrypted", chat),
+            _ => println!("Usage: plain CHAT"),
+        },
+        _ => return false,
+    }
+    true
+}
+
+async fn send<S: ChatStream>(mut send: S, files: Arc<Files>, keys: Arc<RoomKeys>) -> ChatResult<()> {
+    println!("Options: \nJoin CHAT\npost CHAT MESSAGE\nlogin USER\ndm USER MESSAGE\nsearch CHAT WORDS\nreply CHAT ID MESSAGE\nreact CHAT ID EMOJI\nunreact CHAT ID EMOJI\npresence NAME [online|away|offline]\ntyping CHAT\nsend-file CHAT PATH\ndm-file USER PATH\nsave-file ID PATH\nencrypt CHAT PASSPHRASE\nplain CHAT\n(in an encrypted chat only messages are sealed: send-file is refused, and search and @mentions of people who are away don't work)");
+
+    let mut options = io::BufReader::new(io::stdin()).lines();
+
+    while let Some(option_result) = options.next().await {
+        let opt = option_result?;
+        if file_command(&opt, &mut send, &files, &keys).await || key_command(&opt, &keys) {
+            continue;
+        }
+        let req = match parse_input(&opt){
+            Some(req) => req,
+            None => {
+                println!("Unrecognised input: {:?}", opt);
+                continue
+            }
+        };
+        let req = match keys.seal(req) {
+            Ok(req) => req,
+            Err(problem) => {
+                println!("Not sent: {}", problem);
+                continue;
+            }
+        };
+        utils::send_json(&mut send, &req).await?;
+        send.flush().await?;
+    }
+    Ok(())
+}
+
+fn show(packet: Server, files: &Files, keys: &RoomKeys) {
+    match packet {
+        Server::Message { chat_name, id, reply_to, message } if e2e::is_sealed(&message) => {
+            let message = keys.open(&chat_name, message);
+            show(Server::Message { chat_name, id, reply_to, message }, files, keys);
+        }
+        Server::Message { chat_name, id, reply_to: None, message } => {
+            println!("Chat Name: {}\n, Message [{}]: {}\n", chat_name, id, message);
+        }
+        Server::Message { chat_name, id, reply_to: Some(reply_to), message } => {
+            println!("Chat Name: {}\n, Message [{}] replying to [{}]: {}\n", chat_name, id, reply_to, message);
+        }
+        Server::Reactions { chat_name, message_id, counts } => {
+            let counts: Vec<String> = counts.iter().map(|(emoji, count)| format!("{} {}", emoji, count)).collect();
+            println!("Reactions on [{}] in {}: {}", message_id, chat_name, counts.join(", "));
+        }
+        Server::File { chat_name, packet } => {
+            if let Some(news) = files.on_packet(&chat_name, packet) {
+                println!("{}", news);
+            }
+        }
+        Server::DirectFile { from, packet } => {
+            if let Some(news) = files.on_packet(&format!("a direct message from {}", from), packet) {
+                println!("{}", news);
+            }
+        }
+        Server::Presence { chat_name, name, state } => {
+            println!("{} is {:?} in {}", name, state, chat_name);
+        }
+        Server::Typing { chat_name, name } => {
+            println!("{} is typing in {}...", name, chat_name);
+        }
+        Server::Direct { from, message } => {
+            println!("Direct from {}: {}", from, message);
+        }
+        Server::Missed { sent_at, packet } => {
+            print!("While you were away ({}) - ", ago(sent_at));
+            show(*packet, files, keys);
+        }
+        Server::Found { chat_name, query, results } => {
+            println!("{} found in {} for {:?}:", results.len(), chat_name, query);
+            for found in results {
+                println!("  ({}) {}", ago(found.sent_at), keys.open(&chat_name, found.message));
+            }
+        }
+        Server::FileAnswer { id, refused } => files.on_answer(&id, refused),
+        Server::PeerWelcome { .. } => {} //only sent to servers
+        Server::Error(message) => {
+            println!("Error received: {}", message);
+        }
+    }
+}
+
+//"5m ago" for a time given in seconds since the unix epoch
+fn ago(sent_at: u64) -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    match now.saturating_sub(sent_at) {
+        secs if secs < 60 => format!("{}s ago", secs),
+        secs if secs < 60 * 60 => format!("{}m ago", secs / 60),
+        secs if secs < 24 * 60 * 60 => format!("{}h ago", secs / (60 * 60)),
+        secs => format!("{}d ago", secs / (24 * 60 * 60)),
+    }
+}
+
+async fn messages<S: ChatStream>(server: S, files: Arc<Files>, keys: Arc<RoomKeys>) -> ChatResult<()> {
+    let buf = io::BufReader::new(server);
+    let mut stream = utils::receive(buf);
+
+    while let Some(msg) = stream.next().await {
+        show(msg?, &files, &keys);
+    }
+    Ok(())
+}
+
+// the client either runs interactively (reading commands typed on stdin and printing replies for a human)
+// or, with --script, runs a command file for shell based smoke tests, see script.rs
+#[derive(Parser, Debug)]
+#[command(name = "client", about = "Chat client")]
+struct Cli {
+    /// Server address, host:port or unix:/path/to/socket
+    address: String,
+
+    /// Run commands from this file ("-" for stdin) and print every server packet as a JSON line
+    #[arg(long)]
+    script: Option<String>,
+
+    /// Seconds a script's wait-for waits before failing (the script can change it with `timeout SECS`)
+    #[arg(long, default_value_t = 10.0)]
+    timeout: f64,
+
+    /// Log in as this user straight after connecting, so direct messages and mentions are kept while you're away
+    #[arg(long)]
+    user: Option<String>,
+
+    /// The server's limits.max_message_len, encrypted messages that would go over it once sealed aren't sent
+    #[arg(long, default_value_t = Limits::default().max_message_len)]
+    max_message_len: usize,
+}
+
+async fn run<S: ChatStream>(mut socket: S, cli: &Cli) -> ChatResult<()> {
+    if let Some(user_id) = &cli.user {
+        utils::send_json(&mut socket, &Client::Login { user_id: Arc::new(user_id.clone()) }).await?;
+        socket.flush().await?;
+    }
+
+    if let Some(source) = &cli.script {
+        return script::run(socket, source, Duration::from_secs_f64(cli.timeout)).await;
+    }
+
+    let files = Arc::new(Files::default()); //files other members sent us, shared by both halves
+    let keys = Arc::new(RoomKeys::new(cli.max_message_len)); //passphrases for the encrypted rooms, likewise
+    let send = send(socket.clone(), files.clone(), keys.clone()); // create a new task to send a new message to server
+    let replies = messages(socket, files, keys); // to recieve the message to the server
+
+    replies.race(send).await?; //to race each other, it allows the two tasks, send and replies to run concurrently and then we're waiting for
+    //one of them to complete, either send or replies to complete first, when that happens, we do our logic from there
+    //in the mean time, we just want to see who completes first, so we do 'race' each other
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn connect_unix(path: &str, cli: &Cli) -> ChatResult<()> {
+    let socket = async_std::os::unix::net::UnixStream::connect(path).await?;
+    run(socket, cli).await
+}
+
+#[cfg(not(unix))]
+async fn connect_unix(path: &str, _: &Cli) -> ChatResult<()> {
+    Err(format!("cannot connect to unix:{}: unix domain sockets are not supported on this platform", path).into())
+}
+
+async fn connect(cli: &Cli) -> ChatResult<()> {
+    if let Some(path) = utils::unix_path(&cli.address) {
+        return connect_unix(path, cli).await;
+    }
+
+    let socket = net::TcpStream::connect(&cli.address).await?; //connect on the server using the address and the port
+    socket.set_nodelay(true)?; //it's going to disable Nagle's algorithm to reduce the latency
+    //segments are always sent as soon as possible, even if there's only small amount of data, wbhen it's not set, the data is buffered
+    //until there is a sufficient amount of data to send out, thereby avoiding the frequent sending of packets.
+    //true : send us data asap that way our client are recieing the messageas fast as can, thus avoid any unnessary delays
+
+    run(socket, cli).await
+}
+
+// cargo run --release --bin client localhost:8080
+// cargo run --release --bin client unix:/tmp/chat.sock
+// cargo run --release --bin client -- localhost:8080 --script smoke.chat
+fn main() {
+    let cli = Cli::parse(); //reading from terminal for server's ip address and port it's listening to
+
+    if let Err(error) = task::block_on(connect(&cli)) {
+        eprintln!("Error: {}", error);
+        std::process::exit(script::exit_code(&error));
+    }
+}
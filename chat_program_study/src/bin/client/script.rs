@@ -0,0 +1,224 @@
+use async_std::channel::{self, Receiver, Sender};
+use async_std::prelude::*;
+use async_std::{fs, future, io, task};
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+use chat_program_study::utils::{self, ChatError, ChatResult, ChatStream};
+use chat_program_study::{Client, Server};
+
+use crate::{get_value, parse_input};
+
+// script mode reads one command per line, from a file or from a stdin pipe:
+//
+//   join CHAT                      same as the interactive client
+//   post CHAT MESSAGE              same as the interactive client
+//   presence NAME [STATE]          same as the interactive client
+//   typing CHAT                    same as the interactive client
+//   login USER                     same as the interactive client (or pass --user)
+//   dm USER MESSAGE                same as the interactive client
+//   search CHAT WORDS              same as the interactive client
+//   reply CHAT ID MESSAGE          same as the interactive client
+//   react CHAT ID EMOJI            same as the interactive client (unreact takes it back)
+//   wait-for message CHAT [TEXT]   wait until a message arrives in CHAT (one containing TEXT, if given)
+//   wait-for error [TEXT]          wait until the server reports an error (one containing TEXT, if given)
+//   timeout SECS                   how long the following wait-for commands wait
+//   sleep SECS                     pause before running the next command
+//   # comment
+//
+// every packet the server sends is printed on stdout as one JSON line, diagnostics go to stderr.
+// wait-for looks at the packets in the order they arrived, skipping the ones it doesn't want,
+// but an error from the server that nobody waited for fails the script.
+//
+// exit codes: 0 the script passed, 1 it failed (timeout, unexpected error, lost connection), 2 the script itself is wrong
+
+#[derive(Debug)]
+pub struct ScriptError {
+    code: i32,
+    message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for ScriptError {}
+
+fn bad_script(message: String) -> ChatError {
+    Box::new(ScriptError { code: 2, message })
+}
+
+fn failed(message: String) -> ChatError {
+    Box::new(ScriptError { code: 1, message })
+}
+
+pub fn exit_code(error: &ChatError) -> i32 {
+    error.downcast_ref::<ScriptError>().map(|e| e.code).unwrap_or(1)
+}
+
+enum Step {
+    Send(Client),
+    WaitFor(Expect),
+    Timeout(Duration),
+    Sleep(Duration),
+    Nothing,
+}
+
+enum Expect {
+    Message { chat_name: String, containing: Option<String> },
+    Error { containing: Option<String> },
+}
+
+impl Expect {
+    fn matches(&self, packet: &Server) -> bool {
+        let contains = |text: &str, wanted: &Option<String>| wanted.as_ref().is_none_or(|w| text.contains(w.as_str()));
+
+        match (self, packet) {
+            (Expect::Message { chat_name, containing }, Server::Message { chat_name: got, message, .. }) => {
+                got.as_str() == chat_name && contains(message, containing)
+            }
+            (Expect::Error { containing }, Server::Error(message)) => contains(message, containing),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Expect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expect::Message { chat_name, containing: None } => write!(f, "a message in {}", chat_name),
+            Expect::Message { chat_name, containing: Some(text) } => write!(f, "a message in {} containing {:?}", chat_name, text),
+            Expect::Error { containing: None } => write!(f, "an error"),
+            Expect::Error { containing: Some(text) } => write!(f, "an error containing {:?}", text),
+        }
+    }
+}
+
+fn optional_text(rest: &str) -> Option<String> {
+    let text = rest.trim();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text.to_string())
+    }
+}
+
+fn parse_seconds(rest: &str) -> Result<Duration, String> {
+    rest.trim()
+        .parse::<f64>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| format!("expected a number of seconds, got {:?}", rest.trim()))
+}
+
+fn parse_expect(rest: &str) -> Result<Expect, String> {
+    match get_value(rest) {
+        Some(("message", rest)) => match get_value(rest) {
+            Some((chat, rest)) => Ok(Expect::Message { chat_name: chat.to_string(), containing: optional_text(rest) }),
+            None => Err("wait-for message needs a chat name".to_string()),
+        },
+        Some(("error", rest)) => Ok(Expect::Error { containing: optional_text(rest) }),
+        _ => Err("expected `wait-for message CHAT [TEXT]` or `wait-for error [TEXT]`".to_string()),
+    }
+}
+
+fn parse_step(line: &str) -> Result<Step, String> {
+    let line = line.trim();
+    if line.starts_with('#') {
+        return Ok(Step::Nothing);
+    }
+
+    match get_value(line) {
+        None => Ok(Step::Nothing),
+        Some(("wait-for", rest)) => parse_expect(rest).map(Step::WaitFor),
+        Some(("timeout", rest)) => parse_seconds(rest).map(Step::Timeout),
+        Some(("sleep", rest)) => parse_seconds(rest).map(Step::Sleep),
+        Some(_) => parse_input(line).map(Step::Send).ok_or_else(|| format!("unrecognised command {:?}", line)),
+    }
+}
+
+async fn open(source: &str) -> ChatResult<Box<dyn io::BufRead + Send + Unpin>> {
+    if source == "-" {
+        return Ok(Box::new(io::BufReader::new(io::stdin())));
+    }
+    let file = fs::File::open(source)
+        .await
+        .map_err(|e| bad_script(format!("cannot open script {}: {}", source, e)))?;
+    Ok(Box::new(io::BufReader::new(file)))
+}
+
+//prints every packet as a JSON line and hands it on to whichever wait-for is running
+async fn forward_packets<S: ChatStream>(socket: S, packets: Sender<Server>) -> ChatResult<()> {
+    let mut from_server = utils::receive(io::BufReader::new(socket));
+
+    while let Some(packet) = from_server.next().await {
+        let packet: Server = packet?;
+        println!("{}", serde_json::to_string(&packet)?);
+
+        if packets.send(packet).await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+async fn wait_for(packets: &Receiver<Server>, expect: &Expect, timeout: Duration) -> Result<(), String> {
+    let search = async {
+        loop {
+            match packets.recv().await {
+                Ok(packet) if expect.matches(&packet) => return Ok(()),
+                Ok(Server::Error(message)) => return Err(format!("unexpected error from the server: {}", message)),
+                Ok(_) => continue,
+                Err(_) => return Err(format!("connection closed while waiting for {}", expect)),
+            }
+        }
+    };
+
+    future::timeout(timeout, search)
+        .await
+        .unwrap_or_else(|_| Err(format!("timed out after {:.1}s waiting for {}", timeout.as_secs_f64(), expect)))
+}
+
+pub async fn run<S: ChatStream>(mut socket: S, source: &str, mut timeout: Duration) -> ChatResult<()> {
+    let (sender, packets) = channel::unbounded();
+    let reader = task::spawn(forward_packets(socket.clone(), sender));
+
+    let mut lines = open(source).await?.lines();
+    let mut line_no = 0;
+
+    while let Some(line) = lines.next().await {
+        let line = line?;
+        line_no += 1;
+
+        let step = parse_step(&line).map_err(|e| bad_script(format!("{}:{}: {}", source, line_no, e)))?;
+        match step {
+            Step::Send(request) => {
+                utils::send_json(&mut socket, &request).await?;
+                socket.flush().await?;
+            }
+            Step::WaitFor(expect) => {
+                if let Err(problem) = wait_for(&packets, &expect, timeout).await {
+                    //the reader's own error (bad JSON, broken socket) says more than "connection closed"
+                    if packets.is_closed() && packets.is_empty() {
+                        reader.await?;
+                    }
+                    return Err(failed(format!("{}:{}: {}", source, line_no, problem)));
+                }
+            }
+            Step::Timeout(duration) => timeout = duration,
+            Step::Sleep(duration) => task::sleep(duration).await,
+            Step::Nothing => {}
+        }
+    }
+
+    //anything still queued arrived after the last wait-for, it only matters if it was an error
+    while let Ok(packet) = packets.try_recv() {
+        if let Server::Error(message) = packet {
+            return Err(failed(format!("unexpected error from the server: {}", message)));
+        }
+    }
+    Ok(())
+}
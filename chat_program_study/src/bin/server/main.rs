@@ -1,81 +1,119 @@
-use async_std::net;
 use async_std::task;
+use chat_program_study::server::config::{LogFormat, LogLevel, Logging, ServerConfig};
+use chat_program_study::server::start_server;
 use chat_program_study::utils::ChatResult;
-use std::sync::Arc;
-use async_std::prelude::*;
-
-mod connection;
-mod chats;
-mod chats_map;
-
-use connection::handle;
+use clap::Parser;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(name = "server", about = "Async chat server")]
+struct Cli {
+    /// Address to listen on, e.g. localhost:8080 or unix:/tmp/chat.sock (same as a single --listen)
+    address: Option<String>,
+
+    /// Path to a TOML configuration file
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Address to listen on (host:port or unix:/path), can be given more than once
+    #[arg(short, long = "listen")]
+    listen: Vec<String>,
+
+    /// Number of messages a room buffers before slow members start dropping them
+    #[arg(long)]
+    room_capacity: Option<usize>,
+
+    /// Directory the server keeps its data in
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+
+    /// error, info or debug
+    #[arg(long)]
+    log_level: Option<LogLevel>,
+
+    /// pretty (readable lines) or json (one object per line)
+    #[arg(long)]
+    log_format: Option<LogFormat>,
+}
 
-fn log_error(result: ChatResult<()>) {
-    if let Err(error) = result {
-        println!("Error: {}", error);
+//defaults, then the config file, then command line flags on top
+fn load_config(cli: Cli) -> ChatResult<ServerConfig> {
+    let mut config = match &cli.config {
+        Some(path) => ServerConfig::from_file(path)?,
+        None => ServerConfig::default(),
+    };
+
+    let mut listen = cli.listen;
+    listen.extend(cli.address);
+    if !listen.is_empty() {
+        config.listen = listen;
+    }
+    if let Some(capacity) = cli.room_capacity {
+        config.room_capacity = capacity;
+    }
+    if let Some(dir) = cli.data_dir {
+        config.data_dir = dir;
+    }
+    if let Some(level) = cli.log_level {
+        config.logging.level = level;
     }
+    if let Some(format) = cli.log_format {
+        config.logging.format = format;
+    }
+
+    config.validate()?;
+    Ok(config)
 }
 
 // the server(main.rs) is responsible for receiving incoming connections from clients and handling their requests.
-// it's composed of four files
-// main is the entry point of the server and is responsible for setting up the servers listening socket (net::TcpListener::bind(addr).await?;) as well as
-// accepting incoming connections (new_connections = listener.incoming();)
-// spawn new threads (async_std::task::block_on(async {) for each incoming connections
-// each thread created in main is responsible for processing incoming requests from single clients
-
-// connection.rs is used to represent a single connection between the server and a client.
-// it contains methods to read and write data to and from the client, as well as to handle specific requests
-// such as sending messages to the chat room (handle method)
-
-// chats.rs contains the logic for managing our chat rooms. It provides methods for creating new chat rooms
-// and sending messages to the chat rooms
-
-// chats_maps.rs which is responsible for maintaining and mapping of our chat room IDs to the actual chat objects.
-
-//client.rs is responsible for connecting to the server and sending requests to it.
-//it's not doing too much. we are just trying to connect to our server and then send messages to a specified chat room and then
-
-//server and client both utilise lib.rs and utils.rs modules
-
-//lib.rs contains our common functionality shared between the server and the client, such as
-//the message structures and the error handling
-
-//utils.rs contains helper functions used by both the server and client, such as functions for serializing and deserializing data
-
-//so overall to summarize this, the client and the server work together by establishing a connection
-//to each other with the client, sending requests to the server and the server responding to those requests.
-//the server manages the chat rooms using the chats and the chats_map modules while the client sends requests
-//to join chat rooms and sends messages using the client module.
+// the code itself lives in the library under src/server/ (so the integration tests can start it in-process),
+// this binary only reads the command line and the config file and then calls start_server
+// the library side is split into
+// mod.rs binds every listen address (tcp or unix:), accepts connections and spawns a task for each,
+// and holds what they share (ServerHandle is what the tests get back)
+// connection.rs handles a single connection: reads its requests, answers them and writes packets back
+// chats.rs is one chat room, its members, history id counter and reactions, chats_map.rs maps names to rooms
+// history.rs keeps each room's messages on disk along with the search index
+// users.rs remembers logged in users and queues direct messages and mentions while they're away
+// files.rs checks and passes on file transfers, presence.rs tracks who is online, typing or away
+// federation.rs links this server to its peers and relays the shared rooms between them
+// config.rs is the toml config with its defaults and validation
+
+//the client (src/bin/client/) connects to the server and sends it requests, both sides use lib.rs for the
+//packet types and utils.rs for reading and writing them
 
 //cargo run --release --bin server localhost:8080
+//cargo run --release --bin server -- --config server.toml
+//cargo run --release --bin server -- --listen localhost:8080 --listen unix:/tmp/chat.sock
+
+//the library only emits spans and events, this decides where they go: stdout, filtered by level, in the chosen format
+fn init_tracing(logging: &Logging) {
+    let subscriber = tracing_subscriber::fmt().with_max_level(logging.level.tracing_level());
+    match logging.format {
+        LogFormat::Pretty => subscriber.init(),
+        LogFormat::Json => subscriber.json().with_current_span(true).with_span_list(true).init(),
+    }
+}
 
-fn main() -> ChatResult<()> { //what's the significance of returning something out of main function???
-    let addr = std::env::args().nth(1).expect("Server ADDRESS");
+fn main() { //configuration problems are reported as readable messages and a non zero exit code, rather than a panic
+    let config = match load_config(Cli::parse()) {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(2);
+        }
+    };
 
-    let chat_table = Arc::new(chats_map::ChatTracker::new());
-    //we are creating a shared thread, safe data structure to store our chat rooms and our chat table.
-    //so another words, this means that we're creating a thread, safe reference counting pointer that can
-    //shared across multiple thread
+    init_tracing(&config.logging);
 
     //we want to start the server and we'll start it using an async standard
-    async_std::task::block_on(async {
-        let listener = net::TcpListener::bind(addr).await?;
-        let mut new_connections = listener.incoming();
-        //this is going to create a stream of incoming TCP connections by calling the incoming method on the TCP listner
-
-        while let Some(socket_result) = new_connections.next().await {
-            let socket = socket_result?; //this is going to unwrap the next connection in the stream and which will produce a TCP
-            //stream if it was a successful connection or it's going to propagate any
-            let chats = chat_table.clone(); //this clones the chat tracket data structure so that the connection
-            // handler can access the same chat tracker instance as other handlers running concurrently.
-
-            task::spawn(async {//this spawns a new asyc task that calls the handle function defined in the connection
-                // module to handle the incoming connections
-                log_error(handle(socket, chats).await); //log error logs an error happens during handling of the connection
-            }); //task spawn is creating a new task to execute the future, which allows the program
-            // to handle multiple connections concurrently for us
-        }
-        Ok(())
-    })
-}
+    let result = task::block_on(async {
+        let server = start_server(config).await?;
+        server.wait().await
+    });
 
+    if let Err(error) = result {
+        eprintln!("Error: {}", error);
+        std::process::exit(1);
+    }
+}
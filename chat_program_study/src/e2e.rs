@@ -0,0 +1,59 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use sha2::Sha256;
+
+// end-to-end encrypted rooms, done entirely by the clients: everyone in the room types the same passphrase,
+// derives the same key from it, and posts are sealed before they leave the client and opened after they
+// arrive. to the server an encrypted post is just a message body like any other, it relays it unchanged.
+//
+//   key     = PBKDF2-HMAC-SHA256(passphrase, salt = "chat-e2e-room:" + room name, 100_000 rounds)
+//   message = "e2e:v1:" + base64(nonce || ChaCha20-Poly1305(key, nonce, body, associated data = room name))
+//
+// salting with the room name means the same passphrase gives each room its own key, and using it as the
+// associated data means a post copied into another room won't open there.
+
+const PREFIX: &str = "e2e:v1:";
+const ROUNDS: u32 = 100_000;
+const NONCE_LEN: usize = 12;
+
+pub struct RoomKey(ChaCha20Poly1305);
+
+//whether a message body is something RoomKey::seal produced
+pub fn is_sealed(message: &str) -> bool {
+    message.starts_with(PREFIX)
+}
+
+impl RoomKey {
+    //slow on purpose (that's what makes guessing passphrases expensive), so derive once per room and keep it
+    pub fn derive(chat_name: &str, passphrase: &str) -> RoomKey {
+        let salt = format!("chat-e2e-room:{}", chat_name);
+        let mut key = [0u8; 32];
+        pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt.as_bytes(), ROUNDS, &mut key);
+        RoomKey(ChaCha20Poly1305::new(Key::from_slice(&key)))
+    }
+
+    pub fn seal(&self, chat_name: &str, message: &str) -> String {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng); //fresh for every message
+        let payload = Payload { msg: message.as_bytes(), aad: chat_name.as_bytes() };
+        let ciphertext = self.0.encrypt(&nonce, payload).expect("encrypting into a Vec cannot fail");
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        format!("{}{}", PREFIX, BASE64.encode(sealed))
+    }
+
+    //None when the message wasn't sealed with this key for this room, or was changed on the way
+    pub fn open(&self, chat_name: &str, message: &str) -> Option<String> {
+        let sealed = BASE64.decode(message.strip_prefix(PREFIX)?).ok()?;
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+
+        let payload = Payload { msg: ciphertext, aad: chat_name.as_bytes() };
+        let plaintext = self.0.decrypt(Nonce::from_slice(nonce), payload).ok()?;
+        String::from_utf8(plaintext).ok()
+    }
+}
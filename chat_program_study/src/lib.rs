@@ -1,5 +1,8 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::sync::Arc;
+pub mod e2e;
+pub mod server;
 pub mod utils;
 
 #[derive(Debug, Deserialize, Serialize, PartialEq)] // partialEq that is used to define partial equality between two values of the same type
@@ -11,6 +14,58 @@ pub enum Client {
     Post { //post variant
         chat_name: Arc<String>,
         message: Arc<String>
+    },
+    //the two below are only sent by another chat server over a peer link (server-to-server federation)
+    PeerHello { //first thing on a peer link, says which server is on the other end
+        server_id: Arc<String>,
+        secret: Arc<String> //federation.secret, which every server in the federation shares
+    },
+    Relay { //a post made on the `origin` server in a room both servers share
+        origin: Arc<String>,
+        chat_name: Arc<String>,
+        message: Arc<String>
+    },
+    File { //one step of sending a file to everyone in a room
+        chat_name: Arc<String>,
+        packet: FilePacket
+    },
+    DirectFile { //one step of sending a file to one user, needs a Login. they have to be connected, files aren't kept
+        to: Arc<String>,
+        packet: FilePacket
+    },
+    Presence { //says who this connection is and whether they're around, shown in every room it has joined. after a Login the name must be the user id
+        name: Arc<String>,
+        state: PresenceState
+    },
+    Typing { //"i'm writing something", send it again every few seconds while that's still true
+        chat_name: Arc<String>
+    },
+    Login { //who is on this connection, sent once, straight after connecting. anything missed while away comes back first. it also becomes the Presence name
+        user_id: Arc<String>
+    },
+    Direct { //a message for one user, kept for them if they aren't connected
+        to: Arc<String>,
+        message: Arc<String>
+    },
+    Search { //the newest `limit` posts in a room containing every word in `query`, for members of the room
+        chat_name: Arc<String>,
+        query: Arc<String>,
+        limit: usize
+    },
+    Reply { //a post answering the message with id `reply_to`
+        chat_name: Arc<String>,
+        reply_to: u64,
+        message: Arc<String>
+    },
+    React { //adds an emoji to a message, needs a Login so each user counts once
+        chat_name: Arc<String>,
+        message_id: u64,
+        emoji: Arc<String>
+    },
+    Unreact { //takes your emoji back off a message
+        chat_name: Arc<String>,
+        message_id: u64,
+        emoji: Arc<String>
     }
 }
 
@@ -18,7 +73,99 @@ pub enum Client {
 pub enum Server {
     Message {
         chat_name: Arc<String>,
+        id: u64, //given by the server, what Reply and React point at
+        reply_to: Option<u64>, //set when this message answers another one, so clients can show threads
         message: Arc<String>
     },
+    Reactions { //every reaction on a message, sent each time one is added or taken back
+        chat_name: Arc<String>,
+        message_id: u64,
+        counts: BTreeMap<Arc<String>, usize> //emoji -> how many users
+    },
+    File { //the server passes a sender's FilePackets on to the room unchanged
+        chat_name: Arc<String>,
+        packet: FilePacket
+    },
+    DirectFile { //likewise to every connection of the user they were sent to
+        from: Arc<String>,
+        packet: FilePacket
+    },
+    Presence { //a member changed state, also sent once for every member already there when you join
+        chat_name: Arc<String>,
+        name: Arc<String>,
+        state: PresenceState
+    },
+    Typing { //show `name` as typing for a few seconds, it isn't followed by anything saying they stopped
+        chat_name: Arc<String>,
+        name: Arc<String>
+    },
+    Direct {
+        from: Arc<String>,
+        message: Arc<String>
+    },
+    Missed { //a Direct, or a Message mentioning you, that arrived while you were offline. delivered on Login
+        sent_at: u64, //seconds since the unix epoch
+        packet: Box<Server>
+    },
+    Found { //the answer to a Search, newest first
+        chat_name: Arc<String>,
+        query: Arc<String>,
+        results: Vec<Found>
+    },
+    FileAnswer { //the answer to an Offer, only to whoever made it. chunks are worth sending when nothing was refused
+        id: Arc<String>,
+        refused: Option<String>
+    },
+    PeerWelcome { //the answer to an accepted PeerHello, the dialing server relays nothing before it
+        server_id: Arc<String>
+    },
     Error(String)
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct Found {
+    pub sent_at: u64, //seconds since the unix epoch
+    pub message: Arc<String>
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+pub enum PresenceState {
+    Online,
+    Away,
+    Offline
+}
+
+// a file goes out as an Offer, then Chunks in order, then Complete (or Cancel at any point).
+// the id is picked by the sender, and the size and sha256 in the offer let receivers check what they put back together
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub enum FilePacket {
+    Offer {
+        id: Arc<String>,
+        name: Arc<String>,
+        size: u64,
+        sha256: Arc<String> //hex
+    },
+    Chunk {
+        id: Arc<String>,
+        seq: u64, //0, 1, 2, ...
+        data: Arc<String> //base64
+    },
+    Complete {
+        id: Arc<String>
+    },
+    Cancel {
+        id: Arc<String>,
+        reason: Arc<String>
+    }
+}
+
+impl FilePacket {
+    pub fn id(&self) -> &Arc<String> {
+        match self {
+            FilePacket::Offer { id, .. }
+            | FilePacket::Chunk { id, .. }
+            | FilePacket::Complete { id }
+            | FilePacket::Cancel { id, .. } => id,
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,261 @@
+use async_std::task;
+use super::connection::Leaving;
+use super::history::History;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast; //tokio is a crate for writing reliable, async and multithreaded rust applications
+//it provides tools for tasks, networking and input and output and allows rust programs to run efficiently
+//on modern hardware architecture
+//Tokio is built on top of the async and await language features in Rust and it provides us a powerful
+//and scalable foundation for building high performance network services and applications
+
+//so tokio is a great tool to use when building async utilities for networking and IO
+
+use crate::{Client, FilePacket, PresenceState, Server};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{warn, Instrument};
+
+//what goes through a room's broadcast channel
+#[derive(Clone, Debug)]
+pub struct Post {
+    pub origin: Option<Arc<String>>, //None when posted on this server, otherwise the id of the server it came from
+    pub reply_to: Option<u64>, //the id of the message this one answers
+    pub message: Arc<String>,
+}
+
+//everything a room broadcasts to its members
+#[derive(Clone, Debug)]
+pub enum RoomEvent {
+    Post { id: u64, post: Post },
+    Reactions { message_id: u64, counts: BTreeMap<Arc<String>, usize> },
+    File(FilePacket),
+    Presence { name: Arc<String>, state: PresenceState },
+    Typing { name: Arc<String> },
+}
+
+// presence and typing share the broadcast channel with the posts, so they are coalesced before they go in:
+// a presence event is only sent when a member's state actually changes, and a member's typing notices are
+// let through at most once every typing_interval (clients keep showing "typing" for about that long).
+// neither is written anywhere, a room only remembers the current state of each member so newcomers can be told.
+//
+// every post gets an id from its room, which replies and reactions then point at. ids count up from the time
+// the room was created (in microseconds), so they keep increasing across restarts and an id from before one
+// is never handed out again. they are only good on this server, a post relayed from a peer gets a new one here.
+// reactions are only kept for the newest max_reacted_messages posts, older ones can still be replied to
+// but no longer reacted to, and what they had is forgotten.
+
+const MAX_EMOJI_LEN: usize = 32;
+
+type Reactions = BTreeMap<Arc<String>, BTreeSet<Arc<String>>>; //emoji -> the users who reacted with it
+
+fn now_micros() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_micros() as u64).unwrap_or(0)
+}
+
+pub struct Chats { //a chatroom that contains chats?
+    name: Arc<String>,
+    publisher: broadcast::Sender<RoomEvent>, //broadcasting channel that sends reference counting pointer across the broadcast
+    presence: Mutex<HashMap<Arc<String>, PresenceState>>, //members that aren't offline
+    last_typing: Mutex<HashMap<Arc<String>, Instant>>,
+    typing_interval: Duration,
+    history: History, //every post is written to disk so it can be searched later
+    first_id: u64,
+    next_id: AtomicU64,
+    reaction_window: u64,
+    reactions: Mutex<BTreeMap<u64, Reactions>>, //by message id, so the oldest come first
+}
+
+impl Chats {
+    pub fn new(name: Arc<String>, capacity: usize, typing_interval: Duration, reaction_window: usize, history: History) -> Chats { //name is chatroom name
+        let (publisher, _) = broadcast::channel(capacity); //broadcast sender for sending messages, buffering up to `room_capacity` messages
+        let now = now_micros();
+        Chats {
+            name,
+            publisher,
+            presence: Mutex::new(HashMap::new()),
+            last_typing: Mutex::new(HashMap::new()),
+            typing_interval,
+            history,
+            first_id: now,
+            next_id: AtomicU64::new(now),
+            reaction_window: reaction_window as u64,
+            reactions: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn name(&self) -> &Arc<String> {
+        &self.name
+    }
+
+    pub fn join(&self, leaving: Arc<Leaving>) {
+        let receiver = self.publisher.subscribe();
+        //this spawns a new task that listens for new messages, staying in the joining connection's span
+        task::spawn(sub(self.name.clone(), receiver, leaving).instrument(tracing::Span::current()));
+    }
+
+    //forwards the posts made on this server to a peer server, see federation.rs
+    pub fn relay(&self, server_id: Arc<String>, link: Arc<Leaving>) {
+        let receiver = self.publisher.subscribe();
+        task::spawn(relay_sub(self.name.clone(), server_id, receiver, link).instrument(tracing::Span::current()));
+    }
+
+    pub fn post(&self, post: Post) -> u64 { //send message to the publisher, this method takes in the post
+        //and it's going to represent a new message to be broadcasted to all of the chat members
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.history.record(self.name.clone(), post.message.clone());
+        let _ = self.publisher.send(RoomEvent::Post { id, post });
+        id
+    }
+
+    //whether `id` belongs to a post made in this room since it was created
+    pub fn has_message(&self, id: u64) -> bool {
+        id >= self.first_id && id < self.next_id.load(Ordering::SeqCst)
+    }
+
+    //adds (or with `add` false, takes back) `user`'s `emoji` on a message, and tells the room the new counts
+    pub fn react(&self, message_id: u64, emoji: Arc<String>, user: Arc<String>, add: bool) -> Result<(), String> {
+        if !self.has_message(message_id) {
+            return Err(format!("No message {} in {}", message_id, self.name));
+        }
+        let oldest = self.next_id.load(Ordering::SeqCst).saturating_sub(self.reaction_window);
+        if message_id < oldest {
+            return Err(format!("Message {} in {} is too old to react to", message_id, self.name));
+        }
+        if emoji.is_empty() || emoji.len() > MAX_EMOJI_LEN || emoji.contains(char::is_whitespace) {
+            return Err(format!("A reaction is one emoji of up to {} bytes", MAX_EMOJI_LEN));
+        }
+
+        let mut reactions = self.reactions.lock().unwrap();
+        *reactions = reactions.split_off(&oldest); //the posts that have since dropped out of the window
+        let on_message = reactions.entry(message_id).or_default();
+        let changed = if add {
+            on_message.entry(emoji).or_default().insert(user)
+        } else {
+            let removed = on_message.get_mut(&emoji).is_some_and(|users| users.remove(&user));
+            on_message.retain(|_, users| !users.is_empty());
+            removed
+        };
+
+        let counts = on_message.iter().map(|(emoji, users)| (emoji.clone(), users.len())).collect();
+        if on_message.is_empty() {
+            reactions.remove(&message_id);
+        }
+        if changed {
+            let _ = self.publisher.send(RoomEvent::Reactions { message_id, counts });
+        }
+        Ok(())
+    }
+
+    pub fn send_file(&self, packet: FilePacket) {
+        let _ = self.publisher.send(RoomEvent::File(packet));
+    }
+
+    pub fn set_presence(&self, name: Arc<String>, state: PresenceState) {
+        let mut presence = self.presence.lock().unwrap();
+        let previous = match state {
+            PresenceState::Offline => {
+                self.last_typing.lock().unwrap().remove(&name);
+                presence.remove(&name)
+            }
+            _ => presence.insert(name.clone(), state),
+        };
+
+        //nobody needs telling when nothing changed, and a member nobody had seen going offline isn't news either
+        if previous.unwrap_or(PresenceState::Offline) != state {
+            let _ = self.publisher.send(RoomEvent::Presence { name, state });
+        }
+    }
+
+    pub fn typing(&self, name: Arc<String>) {
+        let now = Instant::now();
+        let mut last_typing = self.last_typing.lock().unwrap();
+
+        match last_typing.get(&name) {
+            Some(last) if now.duration_since(*last) < self.typing_interval => {} //the room was told recently enough
+            _ => {
+                last_typing.insert(name.clone(), now);
+                let _ = self.publisher.send(RoomEvent::Typing { name });
+            }
+        }
+    }
+
+    //everyone currently online or away, for someone who has just joined
+    pub fn roster(&self) -> Vec<(Arc<String>, PresenceState)> {
+        self.presence.lock().unwrap().iter().map(|(name, state)| (name.clone(), *state)).collect()
+    }
+}
+
+async fn sub(chat_name: Arc<String>, mut receiver: broadcast::Receiver<RoomEvent>, leaving: Arc<Leaving>) {//what it does is it's going to be the method to send and receive chat messages to our members
+    loop { //this function is going to contain an infinite loop that waits for incoming messages on the
+        //broadcast receiver and we are going to use the recieve method
+        //we are going to block the current task until a message is received
+        //once a message is receieved, it's going to match the result using a match statement and create a new server message struct with the chat room name and message if the message was successsfully recieved.
+        //otherwise, we have RecvError
+        //otherwise, if get an error code, then we have some message that we're going to need to create to send to the chat room.
+
+        let packet = match receiver.recv().await {
+            Ok(RoomEvent::Post { id, post }) => Server::Message {
+                chat_name: chat_name.clone(),
+                id,
+                reply_to: post.reply_to,
+                message: post.message
+            },
+            Ok(RoomEvent::Reactions { message_id, counts }) => Server::Reactions {
+                chat_name: chat_name.clone(),
+                message_id,
+                counts
+            },
+            Ok(RoomEvent::File(packet)) => Server::File {
+                chat_name: chat_name.clone(),
+                packet
+            },
+            Ok(RoomEvent::Presence { name, state }) => Server::Presence {
+                chat_name: chat_name.clone(),
+                name,
+                state
+            },
+            Ok(RoomEvent::Typing { name }) => Server::Typing {
+                chat_name: chat_name.clone(),
+                name
+            },
+            Err(RecvError::Lagged(n)) => {
+                warn!(chat = %chat_name, dropped = n, "member fell behind");
+                Server::Error(format!("Dropped {} message from {}.", n, chat_name))
+            },
+            Err(RecvError::Closed) => break, //because the channel is closed, we need to get out of this loop because the chat no longer exists
+        };
+
+        if leaving.send(packet).await.is_err() { //getting error, we need to break out the loop as well
+            break;
+        }
+    }
+
+}
+
+async fn relay_sub(chat_name: Arc<String>, server_id: Arc<String>, mut receiver: broadcast::Receiver<RoomEvent>, link: Arc<Leaving>) {
+    loop {
+        let relay = match receiver.recv().await {
+            //posts that came in from a peer are never passed on again, that's what stops them going round in circles
+            //files, presence, typing and reactions aren't relayed, they only reach the members on the sender's server.
+            //a reply goes over as a plain post, the id it answers means nothing to the peer
+            Ok(RoomEvent::Post { post: Post { origin: Some(_), .. }, .. }) => continue,
+            Ok(RoomEvent::File(_) | RoomEvent::Presence { .. } | RoomEvent::Typing { .. } | RoomEvent::Reactions { .. }) => continue,
+            Ok(RoomEvent::Post { post: Post { origin: None, message, .. }, .. }) => Client::Relay {
+                origin: server_id.clone(),
+                chat_name: chat_name.clone(),
+                message,
+            },
+            Err(RecvError::Lagged(n)) => { //the peer's members will just miss those, same as a slow member
+                warn!(chat = %chat_name, dropped = n, "peer link fell behind");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+
+        if link.send(relay).await.is_err() { //the link went down, the reconnect subscribes again
+            break;
+        }
+    }
+}
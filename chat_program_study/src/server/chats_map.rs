@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::chats::Chats;
+use super::history::History;
+
+pub struct ChatTracker { //has a mutex hashmap arc string arc chat field
+    //map from the chat room names to the actual chat instances, keep track of all of our chat rooms
+    chats: Mutex< HashMap<Arc<String>, Arc<Chats>> >,
+    room_capacity: usize,
+    max_rooms: usize,
+    typing_interval: Duration,
+    reaction_window: usize,
+    history: History,
+}
+
+impl ChatTracker {
+    pub fn new(room_capacity: usize, max_rooms: usize, typing_interval: Duration, reaction_window: usize, history: History) -> ChatTracker {
+        ChatTracker { chats: Mutex::new(HashMap::new()), room_capacity, max_rooms, typing_interval, reaction_window, history }
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    pub fn find(&self, name: &String) -> Option<Arc<Chats>> { //take in a string reference name and then we need to return arc reference to the
+        // chat instance associated with that name
+        self.chats.lock().unwrap().get(name).cloned()
+    }
+
+    //None once the server already holds max_rooms rooms and `name` isn't one of them
+    pub fn find_or_new(&self, name: Arc<String>) -> Option<Arc<Chats>> {
+        let mut chats = self.chats.lock().unwrap();
+
+        if let Some(chat) = chats.get(&name) {
+            return Some(chat.clone());
+        }
+        if chats.len() >= self.max_rooms {
+            return None;
+        }
+
+        let chat = Arc::new(Chats::new(name.clone(), self.room_capacity, self.typing_interval, self.reaction_window, self.history.clone()));
+        chats.insert(name, chat.clone());
+        Some(chat)
+    }
+}
\ No newline at end of file
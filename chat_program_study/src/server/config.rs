@@ -0,0 +1,232 @@
+use serde::Deserialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::utils::{self, ChatResult};
+
+// the server settings come from three places, each one overriding the previous:
+// built in defaults -> the TOML file given with --config -> command line flags (see bin/server/main.rs)
+// so `server localhost:8080` keeps working exactly like before, while a deployment can keep everything in a file
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub listen: Vec<String>,
+    pub room_capacity: usize,
+    pub data_dir: PathBuf,
+    pub limits: Limits,
+    pub logging: Logging,
+    pub federation: Federation,
+    pub offline: Offline,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub max_connections: usize,
+    pub max_rooms: usize,
+    pub max_chat_name_len: usize,
+    pub max_message_len: usize,
+    pub max_file_size: u64,
+    pub max_chunk_size: usize, //decoded bytes per FilePacket::Chunk
+    pub max_transfers: usize, //files one connection can be sending at the same time
+    pub max_user_name_len: usize,
+    pub typing_interval_ms: u64, //a member's typing notices reach a room at most this often
+    pub max_search_results: usize, //a Search asking for more gets this many
+    pub max_reacted_messages: usize, //reactions are kept for this many of a room's newest posts
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Logging {
+    pub level: LogLevel,
+    pub format: LogFormat,
+}
+
+//links to other chat servers, see federation.rs. both servers list each other under `peers`,
+//since a link only carries posts from the server that dialed it
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Federation {
+    pub server_id: String, //must be different on every server, relayed posts are tagged with it
+    pub secret: String, //the same on every server, a PeerHello without it is refused. unset accepts no peers at all
+    pub peers: Vec<String>,
+    pub shared_rooms: Vec<String>,
+    pub reconnect_delay_ms: u64, //first wait after a link drops, doubled on every failure
+    pub max_reconnect_delay_ms: u64,
+}
+
+//what is kept for users who aren't connected, see users.rs
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Offline {
+    pub max_queued: usize, //per user, the oldest is dropped to make room. 0 keeps nothing
+    pub max_age_secs: u64, //anything older is dropped instead of delivered
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Info,
+    Debug,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            listen: Vec::new(),
+            room_capacity: 1000, //what Chats::new used to hard code
+            data_dir: PathBuf::from("chat_data"),
+            limits: Limits::default(),
+            logging: Logging::default(),
+            federation: Federation::default(),
+            offline: Offline::default(),
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_connections: 1024,
+            max_rooms: 1024,
+            max_chat_name_len: 64,
+            max_message_len: 4096,
+            max_file_size: 10 * 1024 * 1024,
+            max_chunk_size: 64 * 1024,
+            max_transfers: 4,
+            max_user_name_len: 64,
+            typing_interval_ms: 3000,
+            max_search_results: 100,
+            max_reacted_messages: 1000,
+        }
+    }
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Logging { level: LogLevel::Info, format: LogFormat::Pretty }
+    }
+}
+
+impl Default for Federation {
+    fn default() -> Self {
+        Federation {
+            server_id: String::new(),
+            secret: String::new(),
+            peers: Vec::new(),
+            shared_rooms: Vec::new(),
+            reconnect_delay_ms: 1000,
+            max_reconnect_delay_ms: 30_000,
+        }
+    }
+}
+
+impl Default for Offline {
+    fn default() -> Self {
+        Offline { max_queued: 100, max_age_secs: 7 * 24 * 60 * 60 }
+    }
+}
+
+impl Federation {
+    pub fn shares(&self, chat_name: &str) -> bool {
+        self.shared_rooms.iter().any(|room| room == chat_name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Pretty, //one readable line per event, with the spans it happened in
+    Json, //one JSON object per line, for log collectors
+}
+
+impl LogLevel {
+    pub fn tracing_level(self) -> tracing::Level {
+        match self {
+            LogLevel::Error => tracing::Level::ERROR,
+            LogLevel::Info => tracing::Level::INFO,
+            LogLevel::Debug => tracing::Level::DEBUG,
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LogLevel::Error => "error",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+        };
+        f.write_str(name)
+    }
+}
+
+impl ServerConfig {
+    pub fn from_file(path: &Path) -> ChatResult<ServerConfig> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        let config = toml::from_str(&text).map_err(|e| format!("invalid {}: {}", path.display(), e))?;
+        Ok(config)
+    }
+
+    //collects every problem rather than stopping at the first one, so a broken file can be fixed in one go
+    pub fn validate(&self) -> ChatResult<()> {
+        let mut problems = Vec::new();
+
+        if self.listen.is_empty() {
+            problems.push("no listen address given (pass ADDRESS, --listen or set `listen` in the config file)".to_string());
+        }
+        if self.listen.iter().any(|addr| addr.trim().is_empty()) {
+            problems.push("listen addresses cannot be empty".to_string());
+        }
+        if self.listen.iter().any(|addr| utils::unix_path(addr) == Some("")) {
+            problems.push("unix listen addresses need a path, e.g. unix:/tmp/chat.sock".to_string());
+        }
+        if self.room_capacity == 0 {
+            problems.push("room_capacity must be at least 1".to_string());
+        }
+        if self.data_dir.as_os_str().is_empty() {
+            problems.push("data_dir cannot be empty".to_string());
+        }
+        for (name, value) in [
+            ("limits.max_connections", self.limits.max_connections),
+            ("limits.max_rooms", self.limits.max_rooms),
+            ("limits.max_chat_name_len", self.limits.max_chat_name_len),
+            ("limits.max_message_len", self.limits.max_message_len),
+            ("limits.max_chunk_size", self.limits.max_chunk_size),
+            ("limits.max_transfers", self.limits.max_transfers),
+            ("limits.max_user_name_len", self.limits.max_user_name_len),
+            ("limits.max_search_results", self.limits.max_search_results),
+            ("limits.max_reacted_messages", self.limits.max_reacted_messages),
+        ] {
+            if value == 0 {
+                problems.push(format!("{} must be at least 1", name));
+            }
+        }
+
+        let federation = &self.federation;
+        if (!federation.peers.is_empty() || !federation.shared_rooms.is_empty()) && federation.server_id.trim().is_empty() {
+            problems.push("federation.server_id must be set when peers or shared_rooms are".to_string());
+        }
+        if !federation.peers.is_empty() && federation.secret.is_empty() {
+            problems.push("federation.secret must be set when peers are, they refuse links without it".to_string());
+        }
+        if federation.reconnect_delay_ms == 0 {
+            problems.push("federation.reconnect_delay_ms must be at least 1".to_string());
+        }
+        if federation.max_reconnect_delay_ms < federation.reconnect_delay_ms {
+            problems.push("federation.max_reconnect_delay_ms cannot be less than reconnect_delay_ms".to_string());
+        }
+        if self.offline.max_age_secs == 0 {
+            problems.push("offline.max_age_secs must be at least 1".to_string());
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("invalid server configuration:\n  - {}", problems.join("\n  - ")).into())
+        }
+    }
+}
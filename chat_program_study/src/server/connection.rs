@@ -0,0 +1,237 @@
+use async_std::io::BufReader;
+use async_std::prelude::*;
+use async_std::sync::{Arc, Mutex};
+use crate::utils::{self, ChatResult, ChatStream};
+use crate::{Client, FilePacket, Server};
+use serde::Serialize;
+use tracing::{debug, info, Instrument};
+use super::chats::Post;
+use super::chats_map::ChatTracker;
+use super::federation;
+use super::files::{Target, Transfers};
+use super::presence::Presence;
+use super::users::{self, Session, Users};
+use super::config::ServerConfig;
+
+pub struct Leaving(Mutex<Box<dyn async_std::io::Write + Send + Unpin>>);
+//the leaving struct represents an outbound stream (TCP or unix socket, it doesn't care which)
+//when created, the leaving value takes ownership of the stream and wraps it in a mutex to ensure that
+//one task can use it at a time
+
+impl Leaving { //so the new function creates a new leaving instance with a mutex protected tcp stream and the send
+    //function sends a server packet to the client over that stream. the lock variable represents a locked mutex protected
+    //tcp stream, and the send json function sends  json encoded string to the licnet and then we immediately flush it
+    //away to ensure that any buffered data is sent immediately
+    pub fn new<W>(client: W) -> Leaving
+    where
+        W: async_std::io::Write + Send + Unpin + 'static,
+    {
+        Leaving(Mutex::new(Box::new(client)))
+    }
+
+    pub async fn send<P: Serialize>(&self, packet: P) -> ChatResult<()> { //this right here, server packet (packet: Server), or a Client packet on a peer link
+        let mut lock = self.0.lock().await; //mutex protected tcp stream
+
+        utils::send_json(&mut *lock, &packet).await?; //send the sever packet as a json encoded string to the client (await to finish)
+
+        lock.flush().await?; //flush any remaining buffered data to ensure that it is sent immediately
+
+        Ok(())
+    }
+
+    //sends whatever `first` returns while holding the connection, so no other task's packets can get in before them
+    pub async fn send_first<P, F>(&self, first: F) -> ChatResult<()>
+    where
+        P: Serialize,
+        F: FnOnce() -> Vec<P>,
+    {
+        let mut lock = self.0.lock().await;
+
+        for packet in first() {
+            utils::send_json(&mut *lock, &packet).await?;
+        }
+        lock.flush().await?;
+
+        Ok(())
+    }
+}
+
+//an Offer is always answered, so the sender knows whether to go on with the chunks. the other file packets
+//only hear back when something is wrong
+async fn answer_file(leaving: &Leaving, offer: Option<Arc<String>>, result: Result<(), String>) -> ChatResult<Result<(), String>> {
+    match offer {
+        Some(id) => {
+            leaving.send(Server::FileAnswer { id, refused: result.err() }).await?;
+            Ok(Ok(()))
+        }
+        None => Ok(result),
+    }
+}
+
+fn offer_id(packet: &FilePacket) -> Option<Arc<String>> {
+    matches!(packet, FilePacket::Offer { .. }).then(|| packet.id().clone())
+}
+
+fn react(chats: &ChatTracker, session: &Option<Session>, chat_name: Arc<String>, message_id: u64, emoji: Arc<String>, add: bool) -> Result<(), String> {
+    let session = session.as_ref().ok_or("Log in before reacting")?;
+    match chats.find(&chat_name) {
+        Some(chat) => chat.react(message_id, emoji, session.user_id().clone(), add),
+        None => Err(format!("Chat does not exist: {}", chat_name)),
+    }
+}
+
+//names the request in its span, without the (possibly large) contents
+fn request_kind(request: &Client) -> &'static str {
+    match request {
+        Client::Join { .. } => "join",
+        Client::Post { .. } => "post",
+        Client::PeerHello { .. } => "peer_hello",
+        Client::Relay { .. } => "relay",
+        Client::File { .. } => "file",
+        Client::DirectFile { .. } => "direct_file",
+        Client::Presence { .. } => "presence",
+        Client::Typing { .. } => "typing",
+        Client::Login { .. } => "login",
+        Client::Direct { .. } => "direct",
+        Client::Search { .. } => "search",
+        Client::Reply { .. } => "reply",
+        Client::React { .. } => "react",
+        Client::Unreact { .. } => "unreact",
+    }
+}
+
+//runs inside the connection's span (see serve in mod.rs), every request gets a child span of its own
+pub async fn handle<S: ChatStream>(socket: S, chats: Arc<ChatTracker>, users: Arc<Users>, config: Arc<ServerConfig>) -> ChatResult<()> {
+    let limits = &config.limits;
+    let leaving = Arc::new(Leaving::new(socket.clone()));
+
+    let buffered = BufReader::new(socket);
+    let mut from_client = utils::receive(buffered);
+    //this is creating a new stream of client requests by calling the receive function from the util modules and passing
+    //it the buffered input
+
+    let mut peer: Option<Arc<String>> = None; //set once the other end says it's a peer server
+    let mut transfers = Transfers::new(chats.clone(), users.clone()); //files this connection is sending, cancelled if it drops
+    let mut presence = Presence::new(chats.clone()); //who this connection is shown as, offline everywhere once it drops
+    let mut session: Option<Session> = None; //set by Login
+    let connection_span = tracing::Span::current(); //its `user` field is filled in by Login
+
+    while let Some(req_res)  = from_client.next().await {
+        let request = req_res?;
+        let span = tracing::debug_span!("request", kind = request_kind(&request));
+
+        let result: ChatResult<Result<(), String>> = async { Ok(match request {
+            Client::Join { chat_name } if chat_name.len() > limits.max_chat_name_len => {
+                Err(format!("Chat name is longer than {} bytes", limits.max_chat_name_len))
+            }
+            Client::Join { chat_name } => match chats.find_or_new(chat_name.clone()) {
+                Some(chat) => {
+                    info!(chat = %chat_name, "joined");
+                    chat.join(leaving.clone());
+                    if let Some(session) = &session {
+                        users.joined(session.user_id(), chat_name.clone());
+                    }
+                    for packet in presence.joined(&chat, &chat_name) {
+                        leaving.send(packet).await?;
+                    }
+                    Ok(())
+                }
+                None => Err(format!("Cannot create {}, the server already has {} chats", chat_name, limits.max_rooms)),
+            },
+            Client::Post { message, .. } if message.len() > limits.max_message_len => {
+                Err(format!("Message is longer than {} bytes", limits.max_message_len))
+            }
+            Client::Post { chat_name, message } => match chats.find(&chat_name) {
+                Some(chat) => {
+                    let id = chat.post(Post { origin: None, reply_to: None, message: message.clone() });
+                    debug!(chat = %chat_name, id, bytes = message.len(), "posted");
+                    users.mentions(&chat_name, id, None, &message);
+                    Ok(())
+                }
+                None => Err(format!("Chat does not exist: {}", chat_name)),
+            },
+            Client::Reply { message, .. } if message.len() > limits.max_message_len => {
+                Err(format!("Message is longer than {} bytes", limits.max_message_len))
+            }
+            Client::Reply { chat_name, reply_to, message } => match chats.find(&chat_name) {
+                Some(chat) if chat.has_message(reply_to) => {
+                    let id = chat.post(Post { origin: None, reply_to: Some(reply_to), message: message.clone() });
+                    debug!(chat = %chat_name, id, reply_to, bytes = message.len(), "posted");
+                    users.mentions(&chat_name, id, Some(reply_to), &message);
+                    Ok(())
+                }
+                Some(_) => Err(format!("No message {} in {}", reply_to, chat_name)),
+                None => Err(format!("Chat does not exist: {}", chat_name)),
+            },
+            Client::React { chat_name, message_id, emoji } => react(&chats, &session, chat_name, message_id, emoji, true),
+            Client::Unreact { chat_name, message_id, emoji } => react(&chats, &session, chat_name, message_id, emoji, false),
+            Client::File { chat_name, packet } => {
+                let offer = offer_id(&packet);
+                let result = transfers.handle(limits, Target::Room(chat_name), packet).await;
+                answer_file(&leaving, offer, result).await?
+            }
+            Client::DirectFile { to, packet } => {
+                let offer = offer_id(&packet);
+                let result = match &session {
+                    Some(session) => transfers.handle(limits, Target::User { from: session.user_id().clone(), to }, packet).await,
+                    None => Err("Log in before sending files to users".to_string()),
+                };
+                answer_file(&leaving, offer, result).await?
+            }
+            Client::Presence { name, state } => presence.set(limits, name, state),
+            Client::Typing { chat_name } => presence.typing(&chat_name),
+            Client::Login { user_id } => match (&session, users::check_user_id(limits, &user_id)) {
+                (Some(session), _) => Err(format!("Already logged in as {}", session.user_id())),
+                (None, Err(problem)) => Err(problem),
+                (None, Ok(())) => {
+                    connection_span.record("user", user_id.as_str());
+                    info!(user = %user_id, "logged in");
+                    session = Some(Session::new(users.clone(), user_id.clone(), leaving.clone()));
+                    presence.log_in(user_id.clone());
+                    leaving.send_first(|| users.login(user_id, &leaving, presence.room_names())).await?;
+                    Ok(())
+                }
+            },
+            Client::Direct { message, .. } if message.len() > limits.max_message_len => {
+                Err(format!("Message is longer than {} bytes", limits.max_message_len))
+            }
+            Client::Search { chat_name, .. } if !presence.has_joined(&chat_name) => {
+                Err(format!("Join {} before searching it", chat_name))
+            }
+            Client::Search { chat_name, query, limit } => {
+                let limit = limit.min(limits.max_search_results);
+                match chats.history().search(chat_name.clone(), query.to_string(), limit).await {
+                    Ok(results) => {
+                        leaving.send(Server::Found { chat_name, query, results }).await?;
+                        Ok(())
+                    }
+                    Err(problem) => Err(problem),
+                }
+            }
+            Client::Direct { to, message } => match &session {
+                Some(session) => users.direct(session.user_id().clone(), &to, message).await,
+                None => Err("Log in before sending direct messages".to_string()),
+            },
+            Client::PeerHello { server_id, secret } => match federation::check_hello(&config, &server_id, &secret) {
+                Ok(()) => {
+                    info!(server_id = %server_id, "peer linked");
+                    peer = Some(server_id);
+                    leaving.send(Server::PeerWelcome { server_id: Arc::new(config.federation.server_id.clone()) }).await?;
+                    Ok(())
+                }
+                Err(problem) => Err(problem),
+            },
+            Client::Relay { origin, chat_name, message } => match &peer {
+                Some(_) => federation::accept_relay(&config, &chats, &users, origin, chat_name, message),
+                None => Err("Relay is only accepted on a peer link, send PeerHello first".to_string()),
+            },
+        }) }.instrument(span.clone()).await;
+
+        if let Err(message) = result? {
+            span.in_scope(|| info!(error = %message, "refused"));
+            let report = Server::Error(message);
+            leaving.send(report).await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use async_std::net::{self, Shutdown};
+use async_std::prelude::*;
+use async_std::{io, task};
+use sha2::{Digest, Sha256};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::utils::{self, ChatResult};
+use crate::{Client, Server};
+use super::chats::Post;
+use super::chats_map::ChatTracker;
+use super::config::ServerConfig;
+use super::connection::Leaving;
+use super::users::Users;
+use super::Shared;
+use tracing::{debug, error, info};
+
+// server-to-server federation lets a room span several chat servers.
+// every server dials the peers in its config and sends them, over that connection, the posts made locally
+// in the shared rooms (Client::Relay). the peer treats the link like any other connection: it checks the
+// PeerHello (answering it with PeerWelcome), then posts every relay into its own copy of the room, tagged with the server it came from.
+// a PeerHello has to carry federation.secret, otherwise anyone who can reach the port could relay posts
+// under any origin they like.
+//
+// loop prevention: a post that arrived over a peer link is never relayed again (relay_sub in chats.rs skips
+// anything with an origin), and a relay claiming to come from ourselves is refused. so with every server
+// linked to every other one, each post crosses each link exactly once.
+
+pub fn check_hello(config: &ServerConfig, server_id: &str, secret: &str) -> Result<(), String> {
+    let expected = &config.federation.secret;
+    if expected.is_empty() {
+        return Err("This server accepts no peers (federation.secret is not set)".to_string());
+    }
+    //comparing digests, so how long the match takes says nothing about how much of the secret was right
+    if Sha256::digest(secret.as_bytes()) != Sha256::digest(expected.as_bytes()) {
+        return Err(format!("PeerHello from {} has the wrong secret", server_id));
+    }
+    if server_id.is_empty() {
+        return Err("PeerHello needs a server_id".to_string());
+    }
+    if server_id == config.federation.server_id {
+        return Err(format!("Peer claims to be {}, which is this server (is a server linked to itself?)", server_id));
+    }
+    Ok(())
+}
+
+pub fn accept_relay(config: &ServerConfig, chats: &ChatTracker, users: &Users, origin: Arc<String>, chat_name: Arc<String>, message: Arc<String>) -> Result<(), String> {
+    if *origin == config.federation.server_id {
+        return Err(format!("Refusing a relay that originated here ({}), it has gone round in a loop", origin));
+    }
+    if !config.federation.shares(&chat_name) {
+        return Err(format!("Chat is not shared with peers: {}", chat_name));
+    }
+    if message.len() > config.limits.max_message_len {
+        return Err(format!("Message is longer than {} bytes", config.limits.max_message_len));
+    }
+
+    match chats.find_or_new(chat_name.clone()) {
+        Some(chat) => {
+            let id = chat.post(Post { origin: Some(origin), reply_to: None, message: message.clone() });
+            users.mentions(&chat_name, id, None, &message); //offline members mentioned from another server
+            Ok(())
+        }
+        None => Err(format!("Cannot create {}, the server already has {} chats", chat_name, config.limits.max_rooms)),
+    }
+}
+
+//keeps a link to one peer up for as long as the server runs, reconnecting with a growing delay when it drops
+pub async fn peer_link(addr: String, shared: Shared) {
+    let federation = &shared.config.federation;
+    let mut delay = federation.reconnect_delay_ms;
+
+    loop {
+        match net::TcpStream::connect(addr.as_str()).await {
+            Ok(socket) => {
+                info!(peer = %addr, "peer link is up");
+                delay = federation.reconnect_delay_ms; //it worked, so the next drop starts from the short delay again
+
+                match run_link(socket, &shared).await {
+                    Ok(()) => info!(peer = %addr, reason = "closed by the peer", "peer link is down"),
+                    Err(error) => error!(peer = %addr, reason = %error, "peer link is down"),
+                }
+            }
+            Err(error) => {
+                debug!(peer = %addr, %error, "cannot reach peer");
+            }
+        }
+
+        task::sleep(Duration::from_millis(delay)).await;
+        delay = (delay * 2).min(federation.max_reconnect_delay_ms);
+    }
+}
+
+async fn run_link(socket: net::TcpStream, shared: &Shared) -> ChatResult<()> {
+    let config = &shared.config;
+    let server_id = Arc::new(config.federation.server_id.clone());
+
+    socket.set_nodelay(true)?;
+    let link = Arc::new(Leaving::new(socket.clone()));
+    let secret = Arc::new(config.federation.secret.clone());
+    link.send(Client::PeerHello { server_id: server_id.clone(), secret }).await?;
+
+    //nothing is relayed, and the link doesn't count as up, until the peer has accepted us
+    let mut from_peer = utils::receive(io::BufReader::new(socket.clone()));
+    match from_peer.next().await {
+        Some(Ok(Server::PeerWelcome { server_id })) => info!(peer = %server_id, "peer accepted the link"),
+        Some(Ok(Server::Error(error))) => return Err(format!("the peer refused the link: {}", error).into()),
+        Some(Ok(other)) => return Err(format!("expected PeerWelcome from the peer, got {:?}", other).into()),
+        Some(Err(error)) => return Err(error),
+        None => return Ok(()),
+    }
+
+    for room in &config.federation.shared_rooms {
+        match shared.chats.find_or_new(Arc::new(room.clone())) {
+            Some(chat) => chat.relay(server_id.clone(), link.clone()),
+            None => error!(chat = %room, "cannot share the chat, too many chats"),
+        }
+    }
+
+    shared.peers_up.fetch_add(1, Ordering::SeqCst);
+
+    //the peer never sends us posts on this link, only errors about the relays it didn't like,
+    //so all we do here is log those and notice when the connection goes away
+    let result = loop {
+        match from_peer.next().await {
+            Some(Ok(Server::Error(error))) => error!(%error, "peer refused a relay"),
+            Some(Ok(_)) => {}
+            Some(Err(error)) => break Err(error),
+            None => break Ok(()),
+        }
+    };
+
+    shared.peers_up.fetch_sub(1, Ordering::SeqCst);
+    let _ = socket.shutdown(Shutdown::Both); //so the relay tasks still holding this link fail on their next write and stop
+    result
+}
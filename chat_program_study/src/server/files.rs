@@ -0,0 +1,150 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use std::collections::HashMap;
+use std::sync::Arc;
+use async_std::task;
+
+use crate::FilePacket;
+use super::chats_map::ChatTracker;
+use super::config::Limits;
+use super::users::Users;
+
+// the server doesn't keep files, it checks each FilePacket a connection sends and passes it on to the room,
+// or to the user it was sent to.
+// the checks keep a transfer bounded: an offer has to fit in max_file_size, chunks have to arrive in order,
+// decode, stay under max_chunk_size and never add up to more than the offered size, and Complete is only
+// accepted once every byte has been sent. receivers verify the sha256 themselves once they have it all.
+// a transfer that breaks any of these (or whose sender goes away) is cancelled for everyone receiving it.
+
+// where a transfer's packets go: everyone in a room, or every connection of one user
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    Room(Arc<String>),
+    User { from: Arc<String>, to: Arc<String> },
+}
+
+struct Transfer {
+    target: Target,
+    size: u64,
+    received: u64,
+    next_seq: u64,
+}
+
+//the transfers one connection has in progress
+pub struct Transfers {
+    chats: Arc<ChatTracker>,
+    users: Arc<Users>,
+    in_progress: HashMap<Arc<String>, Transfer>,
+}
+
+impl Transfers {
+    pub fn new(chats: Arc<ChatTracker>, users: Arc<Users>) -> Transfers {
+        Transfers { chats, users, in_progress: HashMap::new() }
+    }
+
+    pub async fn handle(&mut self, limits: &Limits, target: Target, packet: FilePacket) -> Result<(), String> {
+        if let Target::Room(chat_name) = &target {
+            self.chats.find(chat_name).ok_or_else(|| format!("Chat does not exist: {}", chat_name))?;
+        }
+
+        match &packet {
+            FilePacket::Offer { id, name, size, sha256 } => {
+                if self.in_progress.contains_key(id) {
+                    return Err(format!("File {} is already being sent", id));
+                }
+                if self.in_progress.len() >= limits.max_transfers {
+                    return Err(format!("Only {} files can be sent at once", limits.max_transfers));
+                }
+                if *size > limits.max_file_size {
+                    return Err(format!("File {} is {} bytes, the limit is {}", name, size, limits.max_file_size));
+                }
+                if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(format!("File {} needs a hex sha256", name));
+                }
+                //the offer goes first, a user who isn't connected refuses the transfer before it starts
+                deliver(&self.chats, &self.users, &target, packet.clone()).await?;
+                let transfer = Transfer { target, size: *size, received: 0, next_seq: 0 };
+                self.in_progress.insert(id.clone(), transfer);
+                return Ok(());
+            }
+            FilePacket::Chunk { id, seq, data } => {
+                let transfer = self.find(id, &target)?;
+                let problem = match BASE64.decode(data.as_bytes()) {
+                    _ if *seq != transfer.next_seq => Some(format!("chunk {} arrived, expected {}", seq, transfer.next_seq)),
+                    Err(_) => Some("chunk is not valid base64".to_string()),
+                    Ok(bytes) if bytes.len() > limits.max_chunk_size => {
+                        Some(format!("chunk is {} bytes, the limit is {}", bytes.len(), limits.max_chunk_size))
+                    }
+                    Ok(bytes) if transfer.received + bytes.len() as u64 > transfer.size => {
+                        Some(format!("more than the {} bytes offered", transfer.size))
+                    }
+                    Ok(bytes) => {
+                        transfer.received += bytes.len() as u64;
+                        transfer.next_seq += 1;
+                        None
+                    }
+                };
+                if let Some(reason) = problem {
+                    return Err(self.cancel(&target, id, reason).await);
+                }
+            }
+            FilePacket::Complete { id } => {
+                let transfer = self.find(id, &target)?;
+                if transfer.received != transfer.size {
+                    let reason = format!("completed after {} of {} bytes", transfer.received, transfer.size);
+                    return Err(self.cancel(&target, id, reason).await);
+                }
+                self.in_progress.remove(id);
+            }
+            FilePacket::Cancel { id, .. } => {
+                self.find(id, &target)?;
+                self.in_progress.remove(id);
+            }
+        }
+
+        deliver(&self.chats, &self.users, &target, packet).await
+    }
+
+    fn find(&mut self, id: &Arc<String>, target: &Target) -> Result<&mut Transfer, String> {
+        match self.in_progress.get_mut(id) {
+            Some(transfer) if transfer.target == *target => Ok(transfer),
+            Some(_) => Err(format!("File {} was offered to someone else", id)),
+            None => Err(format!("No file {} has been offered", id)),
+        }
+    }
+
+    //drops the transfer and tells whoever was receiving it, returning the error for the sender
+    async fn cancel(&mut self, target: &Target, id: &Arc<String>, reason: String) -> String {
+        self.in_progress.remove(id);
+        let cancel = FilePacket::Cancel { id: id.clone(), reason: Arc::new(reason.clone()) };
+        let _ = deliver(&self.chats, &self.users, target, cancel).await; //a receiver that went away doesn't need telling
+        format!("File {} cancelled: {}", id, reason)
+    }
+}
+
+async fn deliver(chats: &ChatTracker, users: &Users, target: &Target, packet: FilePacket) -> Result<(), String> {
+    match target {
+        Target::Room(chat_name) => match chats.find(chat_name) {
+            Some(chat) => {
+                chat.send_file(packet);
+                Ok(())
+            }
+            None => Err(format!("Chat does not exist: {}", chat_name)),
+        },
+        Target::User { from, to } => users.file(from.clone(), to, packet).await,
+    }
+}
+
+impl Drop for Transfers {
+    //the connection is gone, so nothing it was sending will ever be finished
+    fn drop(&mut self) {
+        for (id, transfer) in self.in_progress.drain() {
+            let cancel = FilePacket::Cancel { id, reason: Arc::new("the sender disconnected".to_string()) };
+            let (chats, users) = (self.chats.clone(), self.users.clone());
+            //drop can't wait for a user's connections to take it, so that happens on a task of its own
+            task::spawn(async move {
+                let _ = deliver(&chats, &users, &transfer.target, cancel).await;
+            });
+        }
+    }
+}
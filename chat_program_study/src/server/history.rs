@@ -0,0 +1,278 @@
+use async_std::task;
+use futures::channel::oneshot;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::{Entry, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::Found;
+
+// every post that goes through Chats::post is kept under data_dir/history, one directory per room:
+//
+//   history/<room>/messages.jsonl   one {"sent_at":..,"message":..} line per post, only ever appended to
+//   history/<room>/postings         the inverted index, one 24 byte record (term hash, offset of the post in
+//                                   messages.jsonl, previous record in the same bucket) per term of every post
+//   history/<room>/heads            BUCKETS slots, the newest record of each bucket
+//
+// terms are the lowercased runs of letters and digits in a post, hashed into one of BUCKETS buckets. each
+// bucket is a chain through postings, newest first, so the posts containing a term are found by walking its
+// bucket and keeping the records with its hash. a search walks the chain of one of its terms, reads back each
+// post found and keeps it when it has every other term too, until it has `limit` of them. nothing is kept in
+// memory between searches, and a room is two files however many words were ever used in it.
+//
+// all the file work happens on one thread, fed through a channel, so posting never waits on the disk
+// and a search is queued behind every post made before it (you can always find what you just said).
+
+const MAX_TERM_LEN: usize = 64; //longer "words" are almost certainly not something anyone searches for
+const BUCKETS: u64 = 4096;
+const RECORD_LEN: u64 = 24;
+
+enum Job {
+    Record { chat_name: Arc<String>, message: Arc<String>, sent_at: u64 },
+    Search { chat_name: Arc<String>, query: String, limit: usize, reply: oneshot::Sender<io::Result<Vec<Found>>> },
+    Stop, //everything queued before it is done, nothing after
+}
+
+#[derive(Serialize, Deserialize)]
+struct Line {
+    sent_at: u64,
+    message: Arc<String>,
+}
+
+
+//cheap to clone, every room holds one
+#[derive(Clone)]
+pub struct History(mpsc::Sender<Job>);
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+//FNV-1a, which unlike the std hasher is the same in every build, the index outlives the binary that wrote it
+fn term_hash(term: &str) -> u64 {
+    term.bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+fn terms(text: &str) -> Vec<String> {
+    let mut terms: Vec<String> = text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty() && term.len() <= MAX_TERM_LEN)
+        .map(str::to_lowercase)
+        .collect();
+    terms.sort();
+    terms.dedup();
+    terms
+}
+
+//room names can hold anything, keep letters, digits, '-' and '_' and escape the rest so they make safe directory names
+fn room_dir(root: &Path, chat_name: &str) -> PathBuf {
+    let mut name = String::new();
+    for byte in chat_name.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' => name.push(byte as char),
+            _ => name.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    root.join(name)
+}
+
+impl History {
+    //the thread is handed back so shutdown can wait for it, see stop
+    pub fn start(root: PathBuf) -> (History, thread::JoinHandle<()>) {
+        let (sender, jobs) = mpsc::channel();
+        let indexer = thread::spawn(move || Indexer { root, rooms: HashMap::new() }.run(jobs));
+        (History(sender), indexer)
+    }
+
+    //waits for every post made so far to be on disk and ends the indexer. the rooms still hold senders
+    //(connections outlive a shutdown), which is why the thread is told to stop rather than left to see the channel close.
+    //posts made afterwards are not kept and searches fail with "not available"
+    pub async fn stop(&self, indexer: thread::JoinHandle<()>) {
+        let _ = self.0.send(Job::Stop);
+        if task::spawn_blocking(move || indexer.join()).await.is_err() {
+            tracing::error!("the history indexer panicked");
+        }
+    }
+
+    pub fn record(&self, chat_name: Arc<String>, message: Arc<String>) {
+        let job = Job::Record { chat_name, message, sent_at: now_secs() };
+        let _ = self.0.send(job);
+    }
+
+    pub async fn search(&self, chat_name: Arc<String>, query: String, limit: usize) -> Result<Vec<Found>, String> {
+        if terms(&query).is_empty() {
+            return Err("Search needs at least one word to look for".to_string());
+        }
+
+        let (reply, results) = oneshot::channel();
+        let job = Job::Search { chat_name, query, limit, reply };
+        self.0.send(job).map_err(|_| "Search is not available".to_string())?;
+
+        match results.await {
+            Ok(Ok(found)) => Ok(found),
+            Ok(Err(error)) => Err(format!("Search failed: {}", error)),
+            Err(_) => Err("Search is not available".to_string()),
+        }
+    }
+}
+
+fn read_u64(file: &mut File, at: u64) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    file.seek(SeekFrom::Start(at))?;
+    file.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn write_u64(file: &mut File, at: u64, value: u64) -> io::Result<()> {
+    file.seek(SeekFrom::Start(at))?;
+    file.write_all(&value.to_le_bytes())
+}
+
+//one room's files, open for writing. records are numbered from 1 in chains and heads, 0 ends a chain
+struct Room {
+    log: File,
+    len: u64, //of messages.jsonl, where the next post goes
+    postings: File,
+    records: u64, //in postings
+    heads: File,
+}
+
+impl Room {
+    fn open(dir: &Path) -> io::Result<Room> {
+        fs::create_dir_all(dir)?;
+        let log = OpenOptions::new().create(true).append(true).open(dir.join("messages.jsonl"))?;
+        let len = log.metadata()?.len();
+
+        //a crash can leave part of a record at the end, it is cut off so the next one starts where it should
+        let postings = OpenOptions::new().create(true).read(true).write(true).truncate(false).open(dir.join("postings"))?;
+        let records = postings.metadata()?.len() / RECORD_LEN;
+        if postings.metadata()?.len() > records * RECORD_LEN {
+            tracing::warn!(dir = %dir.display(), "dropping an unfinished posting");
+            postings.set_len(records * RECORD_LEN)?;
+        }
+
+        let heads = OpenOptions::new().create(true).read(true).write(true).truncate(false).open(dir.join("heads"))?;
+        if heads.metadata()?.len() < BUCKETS * 8 {
+            heads.set_len(BUCKETS * 8)?;
+        }
+        Ok(Room { log, len, postings, records, heads })
+    }
+
+    fn record(&mut self, message: Arc<String>, sent_at: u64) -> io::Result<()> {
+        let mut line = serde_json::to_string(&Line { sent_at, message: message.clone() })?;
+        line.push('\n');
+        self.log.write_all(line.as_bytes())?;
+        let offset = self.len;
+        self.len += line.len() as u64;
+
+        //the message is written before anything points at it, and the records before the heads pointing at
+        //them, so a search never follows a pointer to nowhere
+        let mut records = Vec::new();
+        let mut heads: HashMap<u64, u64> = HashMap::new(); //the buckets this post moves on, two of its terms can share one
+        for term in terms(&message) {
+            let hash = term_hash(&term);
+            let bucket = hash % BUCKETS;
+            let prev = match heads.get(&bucket) {
+                Some(head) => *head,
+                None => read_u64(&mut self.heads, bucket * 8)?,
+            };
+            records.extend_from_slice(&hash.to_le_bytes());
+            records.extend_from_slice(&offset.to_le_bytes());
+            records.extend_from_slice(&prev.to_le_bytes());
+            self.records += 1;
+            heads.insert(bucket, self.records);
+        }
+        self.postings.seek(SeekFrom::End(0))?;
+        self.postings.write_all(&records)?;
+        for (bucket, head) in heads {
+            write_u64(&mut self.heads, bucket * 8, head)?;
+        }
+        Ok(())
+    }
+}
+
+//a room that has never had a post has no files, and searching it finds nothing rather than creating them
+fn open_existing(path: &Path) -> io::Result<Option<File>> {
+    match File::open(path) {
+        Ok(file) => Ok(Some(file)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+//the newest `limit` posts in the room under `dir` with every term of `query`
+fn search(dir: &Path, query: &str, limit: usize) -> io::Result<Vec<Found>> {
+    let (Some(log), Some(mut postings), Some(mut heads)) =
+        (open_existing(&dir.join("messages.jsonl"))?, open_existing(&dir.join("postings"))?, open_existing(&dir.join("heads"))?)
+    else {
+        return Ok(Vec::new());
+    };
+    let mut log = BufReader::new(log);
+    let wanted = terms(query);
+    let Some(first) = wanted.first() else { return Ok(Vec::new()) };
+    let hash = term_hash(first);
+    let records = postings.metadata()?.len() / RECORD_LEN;
+
+    let mut found = Vec::new();
+    let mut next = read_u64(&mut heads, (hash % BUCKETS) * 8)?;
+    while next != 0 && next <= records && found.len() < limit {
+        let record_hash = read_u64(&mut postings, (next - 1) * RECORD_LEN)?;
+        let offset = read_u64(&mut postings, (next - 1) * RECORD_LEN + 8)?;
+        let prev = read_u64(&mut postings, (next - 1) * RECORD_LEN + 16)?;
+        if prev >= next {
+            break; //chains only go back, anything else is damage
+        }
+        next = prev;
+        if record_hash != hash {
+            continue; //another term in the same bucket
+        }
+
+        log.seek(SeekFrom::Start(offset))?;
+        let mut line = String::new();
+        log.read_line(&mut line)?;
+        let Line { sent_at, message } = serde_json::from_str(&line)?;
+        let has = terms(&message);
+        if wanted.iter().all(|term| has.binary_search(term).is_ok()) { //also weeds out the rare hash collision
+            found.push(Found { sent_at, message });
+        }
+    }
+    Ok(found)
+}
+
+struct Indexer {
+    root: PathBuf,
+    rooms: HashMap<Arc<String>, Room>, //opened the first time they're posted in
+}
+
+impl Indexer {
+    fn run(mut self, jobs: mpsc::Receiver<Job>) {
+        for job in jobs {
+            match job {
+                Job::Record { chat_name, message, sent_at } => {
+                    if let Err(error) = self.record(chat_name.clone(), message, sent_at) {
+                        tracing::error!(chat = %chat_name, %error, "cannot index a post");
+                    }
+                }
+                Job::Search { chat_name, query, limit, reply } => {
+                    let found = search(&room_dir(&self.root, &chat_name), &query, limit);
+                    let _ = reply.send(found); //nobody waiting means the connection went away
+                }
+                Job::Stop => return, //dropping the rooms closes their files
+            }
+        }
+    }
+
+    fn record(&mut self, chat_name: Arc<String>, message: Arc<String>, sent_at: u64) -> io::Result<()> {
+        let room = match self.rooms.entry(chat_name) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let room = Room::open(&room_dir(&self.root, entry.key()))?;
+                entry.insert(room)
+            }
+        };
+        room.record(message, sent_at)
+    }
+}
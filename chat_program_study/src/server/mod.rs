@@ -0,0 +1,244 @@
+use async_std::net;
+use async_std::prelude::*;
+use async_std::task::{self, JoinHandle};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn, Instrument};
+
+use crate::utils::{self, ChatResult, ChatStream};
+
+mod chats;
+mod chats_map;
+pub mod config;
+mod connection;
+mod federation;
+mod files;
+mod history;
+mod presence;
+mod users;
+
+use chats_map::ChatTracker;
+use config::ServerConfig;
+use connection::handle;
+use history::History;
+use users::Users;
+
+// the server core lives in the library (rather than in bin/server) so that tests can run a real server
+// in-process: start_server binds every listen address up front, so "127.0.0.1:0" gets an ephemeral port
+// that the returned handle reports back, and the accept loops then run as background tasks
+
+// logging goes through `tracing`: every connection runs in a "connection" span (id, peer, and user once it
+// logs in) and each request in a "request" span inside it. the library only emits spans and events,
+// the server binary picks where they go and in what format (see init_tracing in bin/server/main.rs)
+
+//counts live connections, the slot is given back when the guard is dropped at the end of the connection task
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl ConnectionSlot {
+    fn take(active: &Arc<AtomicUsize>, max: usize) -> Option<ConnectionSlot> {
+        let taken = active.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| if n < max { Some(n + 1) } else { None });
+        taken.ok().map(|_| ConnectionSlot(active.clone()))
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+//everything a connection task needs, cloned into each one
+#[derive(Clone)]
+struct Shared {
+    config: Arc<ServerConfig>,
+    chats: Arc<ChatTracker>,
+    users: Arc<Users>,
+    active: Arc<AtomicUsize>,
+    peers_up: Arc<AtomicUsize>, //peer links currently connected
+    next_connection: Arc<AtomicU64>, //numbers connections for their spans
+}
+
+pub struct ServerHandle {
+    addrs: Vec<String>,
+    accept_loops: Vec<JoinHandle<ChatResult<()>>>,
+    peer_links: Vec<JoinHandle<()>>,
+    indexer: std::thread::JoinHandle<()>, //see History::stop
+    shared: Shared,
+}
+
+impl ServerHandle {
+    //the first address actually bound, with the real port when the config asked for port 0
+    pub fn addr(&self) -> &str {
+        &self.addrs[0]
+    }
+
+    pub fn addrs(&self) -> &[String] {
+        &self.addrs
+    }
+
+    //links to another server on top of the peers in the config, e.g. one whose port was only known after it started
+    pub fn connect_peer(&mut self, addr: &str) {
+        let span = tracing::info_span!("peer_link", peer = %addr);
+        self.peer_links.push(task::spawn(federation::peer_link(addr.to_string(), self.shared.clone()).instrument(span)));
+    }
+
+    pub fn connected_peers(&self) -> usize {
+        self.shared.peers_up.load(Ordering::SeqCst)
+    }
+
+    //runs until one of the listeners fails
+    pub async fn wait(self) -> ChatResult<()> {
+        futures::future::try_join_all(self.accept_loops).await?;
+        Ok(())
+    }
+
+    //stops accepting new connections and dialing peers and waits for the history to be written,
+    //the connections already open are left to finish on their own
+    pub async fn shutdown(self) {
+        for accept_loop in self.accept_loops {
+            accept_loop.cancel().await;
+        }
+        for peer_link in self.peer_links {
+            peer_link.cancel().await;
+        }
+        self.shared.chats.history().stop(self.indexer).await;
+    }
+}
+
+pub async fn start_server(config: ServerConfig) -> ChatResult<ServerHandle> {
+    config.validate()?;
+    std::fs::create_dir_all(&config.data_dir)
+        .map_err(|e| format!("cannot create data_dir {}: {}", config.data_dir.display(), e))?;
+
+    let (history, indexer) = History::start(config.data_dir.join("history"));
+    let shared = Shared {
+        chats: Arc::new(ChatTracker::new(
+            config.room_capacity,
+            config.limits.max_rooms,
+            Duration::from_millis(config.limits.typing_interval_ms),
+            config.limits.max_reacted_messages,
+            history,
+        )),
+        //we are creating a shared thread, safe data structure to store our chat rooms and our chat table.
+        //so another words, this means that we're creating a thread, safe reference counting pointer that can
+        //shared across multiple thread
+        users: Arc::new(Users::new(config.offline.clone())),
+        config: Arc::new(config),
+        active: Arc::new(AtomicUsize::new(0)),
+        peers_up: Arc::new(AtomicUsize::new(0)),
+        next_connection: Arc::new(AtomicU64::new(1)),
+    };
+
+    let mut addrs = Vec::new();
+    let mut accept_loops = Vec::new();
+
+    for addr in &shared.config.listen {
+        let (bound, accept_loop) = match utils::unix_path(addr) {
+            Some(path) => bind_unix(path, shared.clone()).await?,
+            None => bind_tcp(addr, shared.clone()).await?,
+        };
+        info!(address = %bound, "listening");
+        addrs.push(bound);
+        accept_loops.push(accept_loop);
+    }
+
+    let mut server = ServerHandle { addrs, accept_loops, peer_links: Vec::new(), indexer, shared };
+    for peer in server.shared.config.federation.peers.clone() {
+        server.connect_peer(&peer);
+    }
+    Ok(server)
+}
+
+async fn bind_tcp(addr: &str, shared: Shared) -> ChatResult<(String, JoinHandle<ChatResult<()>>)> {
+    let listener = net::TcpListener::bind(addr).await
+        .map_err(|e| format!("cannot listen on {}: {}", addr, e))?;
+    let bound = listener.local_addr()?.to_string();
+
+    let accept_loop = task::spawn(async move {
+        let new_connections = listener.incoming();
+        //this is going to create a stream of incoming TCP connections by calling the incoming method on the TCP listner
+        let peer_name = |socket: &net::TcpStream| socket.peer_addr().map(|a| a.to_string()).unwrap_or_else(|_| "unknown".to_string());
+
+        serve(new_connections, peer_name, shared).await
+    });
+    Ok((bound, accept_loop))
+}
+
+#[cfg(unix)]
+async fn bind_unix(path: &str, shared: Shared) -> ChatResult<(String, JoinHandle<ChatResult<()>>)> {
+    use async_std::os::unix::net::{UnixListener, UnixStream};
+    use std::os::unix::fs::FileTypeExt;
+
+    //a socket file left behind by a previous run would make bind fail, but never delete anything that isn't a socket
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if meta.file_type().is_socket() {
+            std::fs::remove_file(path)?;
+        }
+    }
+
+    let listener = UnixListener::bind(path).await
+        .map_err(|e| format!("cannot listen on unix:{}: {}", path, e))?;
+    let bound = format!("unix:{}", path);
+
+    let name = bound.clone();
+    let accept_loop = task::spawn(async move {
+        //unix peers are usually unnamed, so number them instead
+        let mut count = 0;
+        let peer_name = |_: &UnixStream| {
+            count += 1;
+            format!("{}#{}", name, count)
+        };
+
+        serve(listener.incoming(), peer_name, shared).await
+    });
+    Ok((bound, accept_loop))
+}
+
+#[cfg(not(unix))]
+async fn bind_unix(path: &str, _: Shared) -> ChatResult<(String, JoinHandle<ChatResult<()>>)> {
+    Err(format!("cannot listen on unix:{}: unix domain sockets are not supported on this platform", path).into())
+}
+
+//the part of accepting connections that doesn't care whether they come over TCP or a unix socket
+async fn serve<S, I, P>(mut new_connections: I, mut peer_name: P, shared: Shared) -> ChatResult<()>
+where
+    S: ChatStream,
+    I: Stream<Item = std::io::Result<S>> + Unpin,
+    P: FnMut(&S) -> String,
+{
+    let config = shared.config.clone();
+
+    while let Some(socket_result) = new_connections.next().await {
+        let socket = socket_result?; //this is going to unwrap the next connection in the stream and which will produce a
+        //stream if it was a successful connection or it's going to propagate any
+        let peer = peer_name(&socket);
+
+        let slot = match ConnectionSlot::take(&shared.active, config.limits.max_connections) {
+            Some(slot) => slot,
+            None => {
+                warn!(peer = %peer, "refusing connection, too many connections");
+                continue; //dropping the socket closes it
+            }
+        };
+        let id = shared.next_connection.fetch_add(1, Ordering::SeqCst);
+        let span = tracing::info_span!("connection", id, peer = %peer, user = tracing::field::Empty);
+        span.in_scope(|| debug!("connected"));
+
+        let chats = shared.chats.clone(); //this clones the chat tracket data structure so that the connection
+        // handler can access the same chat tracker instance as other handlers running concurrently.
+        let users = shared.users.clone();
+        let config = config.clone();
+
+        task::spawn(async move {//this spawns a new asyc task that calls the handle function defined in the connection
+            // module to handle the incoming connections
+            match handle(socket, chats, users, config).await { //the reason the connection ended is logged either way
+                Ok(()) => info!(reason = "closed by the client", "disconnected"),
+                Err(error) => info!(reason = %error, "disconnected"),
+            }
+            drop(slot);
+        }.instrument(span)); //task spawn is creating a new task to execute the future, which allows the program
+        // to handle multiple connections concurrently for us
+    }
+    Ok(())
+}
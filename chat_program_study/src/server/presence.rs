@@ -0,0 +1,107 @@
+use std::sync::Arc;
+
+use crate::{PresenceState, Server};
+use super::chats::Chats;
+use super::chats_map::ChatTracker;
+use super::config::Limits;
+
+// a connection becomes somebody by sending Client::Presence with a name, after which its state is shown
+// in every room it has joined (and in the ones it joins later) and it may send Typing.
+// logging in makes the user id that name, so presence and direct messages agree on who someone is.
+// the rooms do the coalescing, see chats.rs, this only remembers who the connection is and where it has been.
+
+pub struct Presence {
+    chats: Arc<ChatTracker>,
+    name: Option<Arc<String>>,
+    user_id: Option<Arc<String>>, //set by Login, the only name presence may use from then on
+    state: PresenceState,
+    rooms: Vec<Arc<Chats>>,
+}
+
+impl Presence {
+    pub fn new(chats: Arc<ChatTracker>) -> Presence {
+        Presence { chats, name: None, user_id: None, state: PresenceState::Offline, rooms: Vec::new() }
+    }
+
+    pub fn set(&mut self, limits: &Limits, name: Arc<String>, state: PresenceState) -> Result<(), String> {
+        if name.trim().is_empty() {
+            return Err("Presence needs a name".to_string());
+        }
+        if name.len() > limits.max_user_name_len {
+            return Err(format!("Name is longer than {} bytes", limits.max_user_name_len));
+        }
+        if let Some(user_id) = self.user_id.as_ref().filter(|user_id| **user_id != name) {
+            return Err(format!("Logged in as {}, presence can only be shown under that name", user_id));
+        }
+        self.show(name, state);
+        Ok(())
+    }
+
+    //whatever name was shown before, the connection is `user_id` now
+    pub fn log_in(&mut self, user_id: Arc<String>) {
+        self.show(user_id.clone(), self.state);
+        self.user_id = Some(user_id);
+    }
+
+    fn show(&mut self, name: Arc<String>, state: PresenceState) {
+        //changing name is leaving under the old one
+        if let Some(old) = self.name.as_ref().filter(|old| **old != name) {
+            for chat in &self.rooms {
+                chat.set_presence(old.clone(), PresenceState::Offline);
+            }
+        }
+        for chat in &self.rooms {
+            chat.set_presence(name.clone(), state);
+        }
+        self.name = Some(name);
+        self.state = state;
+    }
+
+    //the packets telling someone who just joined `chat` who is already there
+    pub fn joined(&mut self, chat: &Arc<Chats>, chat_name: &Arc<String>) -> Vec<Server> {
+        if self.rooms.iter().any(|room| Arc::ptr_eq(room, chat)) {
+            return Vec::new(); //joining twice, they were told the first time
+        }
+
+        let roster = chat.roster().into_iter()
+            .map(|(name, state)| Server::Presence { chat_name: chat_name.clone(), name, state })
+            .collect();
+
+        if let Some(name) = &self.name {
+            chat.set_presence(name.clone(), self.state);
+        }
+        self.rooms.push(chat.clone());
+        roster
+    }
+
+    pub fn has_joined(&self, chat_name: &Arc<String>) -> bool {
+        self.rooms.iter().any(|chat| chat.name() == chat_name)
+    }
+
+    pub fn room_names(&self) -> Vec<Arc<String>> {
+        self.rooms.iter().map(|chat| chat.name().clone()).collect()
+    }
+
+    pub fn typing(&self, chat_name: &Arc<String>) -> Result<(), String> {
+        let name = self.name.as_ref().ok_or("Send a Presence with your name before Typing")?;
+        match self.rooms.iter().find(|chat| chat.name() == chat_name) {
+            Some(chat) => {
+                chat.typing(name.clone());
+                Ok(())
+            }
+            None if self.chats.find(chat_name).is_some() => Err(format!("Join {} before typing in it", chat_name)),
+            None => Err(format!("Chat does not exist: {}", chat_name)),
+        }
+    }
+}
+
+impl Drop for Presence {
+    //the connection is gone, so whoever it was is offline now
+    fn drop(&mut self) {
+        if let Some(name) = &self.name {
+            for chat in &self.rooms {
+                chat.set_presence(name.clone(), PresenceState::Offline);
+            }
+        }
+    }
+}
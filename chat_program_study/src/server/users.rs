@@ -0,0 +1,189 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{FilePacket, Server};
+use super::config::{Limits, Offline};
+use super::connection::Leaving;
+
+// a connection says which user it is with Client::Login. from then on the server remembers that user
+// (in memory, a restart forgets everyone) along with the rooms they have joined, so that while none of
+// their connections are up it can keep what they would have missed:
+//   - direct messages sent to them
+//   - posts in one of their rooms that mention them as @user_id
+// the queue is bounded by offline.max_queued (oldest dropped first) and offline.max_age_secs, and it is
+// handed over on their next Login, before the connection gets anything else. a user who has been gone longer
+// than max_age_secs with nothing left in their queue is forgotten, there is nothing to keep for them that
+// wouldn't have expired anyway, and they start afresh on their next Login.
+
+struct Queued {
+    sent_at: u64,
+    packet: Server,
+}
+
+#[derive(Default)]
+struct User {
+    sessions: Vec<Arc<Leaving>>, //the user's connections that are up right now
+    rooms: HashSet<Arc<String>>,
+    queue: VecDeque<Queued>,
+    last_seen: u64, //when the last of their sessions went
+}
+
+pub struct Users {
+    users: Mutex<HashMap<Arc<String>, User>>,
+    offline: Offline,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+pub fn check_user_id(limits: &Limits, user_id: &str) -> Result<(), String> {
+    if user_id.is_empty() || user_id.contains(char::is_whitespace) {
+        return Err("A user id cannot be empty or contain spaces".to_string());
+    }
+    if user_id.len() > limits.max_user_name_len {
+        return Err(format!("User id is longer than {} bytes", limits.max_user_name_len));
+    }
+    Ok(())
+}
+
+//the @user_ids in a message, "@bob," and "@bob!" both mention bob
+fn mentioned(message: &str) -> HashSet<&str> {
+    message.split_whitespace()
+        .filter_map(|word| word.strip_prefix('@'))
+        .map(|name| name.trim_end_matches(|c: char| c.is_ascii_punctuation()))
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+impl Users {
+    pub fn new(offline: Offline) -> Users {
+        Users { users: Mutex::new(HashMap::new()), offline }
+    }
+
+    //registers `leaving` as one of the user's connections and takes what they missed, call it with the connection
+    //locked (Leaving::send_first) so nothing live can get out ahead of the missed packets
+    pub fn login(&self, user_id: Arc<String>, leaving: &Arc<Leaving>, rooms: Vec<Arc<String>>) -> Vec<Server> {
+        let mut users = self.users.lock().unwrap();
+        let user = users.entry(user_id).or_default();
+
+        user.sessions.push(leaving.clone());
+        user.rooms.extend(rooms);
+
+        let oldest = now_secs().saturating_sub(self.offline.max_age_secs);
+        user.queue.drain(..)
+            .filter(|queued| queued.sent_at >= oldest)
+            .map(|queued| Server::Missed { sent_at: queued.sent_at, packet: Box::new(queued.packet) })
+            .collect()
+    }
+
+    pub fn joined(&self, user_id: &Arc<String>, chat_name: Arc<String>) {
+        if let Some(user) = self.users.lock().unwrap().get_mut(user_id) {
+            user.rooms.insert(chat_name);
+        }
+    }
+
+    //passes a file packet on to every connection `to` has up. unlike direct messages nothing is kept for later,
+    //a file can be large and is made of many packets
+    pub async fn file(&self, from: Arc<String>, to: &Arc<String>, packet: FilePacket) -> Result<(), String> {
+        let sessions = match self.users.lock().unwrap().get(to) {
+            Some(user) if !user.sessions.is_empty() => user.sessions.clone(),
+            Some(_) => return Err(format!("{} is not connected, files can only be sent to users who are", to)),
+            None => return Err(format!("No such user: {}", to)),
+        };
+
+        for session in sessions {
+            let _ = session.send(Server::DirectFile { from: from.clone(), packet: packet.clone() }).await;
+        }
+        Ok(())
+    }
+
+    pub async fn direct(&self, from: Arc<String>, to: &Arc<String>, message: Arc<String>) -> Result<(), String> {
+        let sessions = {
+            let mut users = self.users.lock().unwrap();
+            let user = users.get_mut(to).ok_or_else(|| format!("No such user: {}", to))?;
+
+            if user.sessions.is_empty() {
+                self.queue(user, Server::Direct { from, message });
+                return Ok(());
+            }
+            user.sessions.clone()
+        };
+
+        for session in sessions {
+            //a connection that has just gone is about to take itself off the list, nothing to do about it here
+            let _ = session.send(Server::Direct { from: from.clone(), message: message.clone() }).await;
+        }
+        Ok(())
+    }
+
+    //keeps a post for the members of `chat_name` it mentions who aren't connected, the others see it live
+    pub fn mentions(&self, chat_name: &Arc<String>, id: u64, reply_to: Option<u64>, message: &Arc<String>) {
+        let mut users = self.users.lock().unwrap();
+
+        for name in mentioned(message) {
+            match users.get_mut(&name.to_string()) {
+                Some(user) if user.sessions.is_empty() && user.rooms.contains(chat_name) => {
+                    let packet = Server::Message { chat_name: chat_name.clone(), id, reply_to, message: message.clone() };
+                    self.queue(user, packet);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn queue(&self, user: &mut User, packet: Server) {
+        let now = now_secs();
+        let oldest = now.saturating_sub(self.offline.max_age_secs);
+
+        while user.queue.front().is_some_and(|queued| queued.sent_at < oldest) {
+            user.queue.pop_front();
+        }
+        if self.offline.max_queued == 0 {
+            return;
+        }
+        while user.queue.len() >= self.offline.max_queued {
+            user.queue.pop_front();
+        }
+        user.queue.push_back(Queued { sent_at: now, packet });
+    }
+
+    fn logout(&self, user_id: &Arc<String>, leaving: &Arc<Leaving>) {
+        let now = now_secs();
+        let oldest = now.saturating_sub(self.offline.max_age_secs);
+        let mut users = self.users.lock().unwrap();
+
+        if let Some(user) = users.get_mut(user_id) {
+            user.sessions.retain(|session| !Arc::ptr_eq(session, leaving));
+            user.last_seen = now;
+        }
+        users.retain(|_, user| {
+            user.queue.retain(|queued| queued.sent_at >= oldest);
+            !user.sessions.is_empty() || user.last_seen >= oldest || !user.queue.is_empty()
+        });
+    }
+}
+
+//a logged in connection, taken off its user's sessions when dropped so the user counts as offline again
+pub struct Session {
+    users: Arc<Users>,
+    user_id: Arc<String>,
+    leaving: Arc<Leaving>,
+}
+
+impl Session {
+    pub fn new(users: Arc<Users>, user_id: Arc<String>, leaving: Arc<Leaving>) -> Session {
+        Session { users, user_id, leaving }
+    }
+
+    pub fn user_id(&self) -> &Arc<String> {
+        &self.user_id
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.users.logout(&self.user_id, &self.leaving);
+    }
+}
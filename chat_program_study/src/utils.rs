@@ -18,6 +18,17 @@ pub type ChatError = Box<dyn Error + Send + Sync + 'static>;
 
 pub type ChatResult<T> = Result<T, ChatError>;
 
+//anything the chat protocol can run over, a TcpStream or (on unix) a UnixStream.
+//both of them are cheap handles onto the same socket, so clone gives us separate reading and writing halves
+pub trait ChatStream: async_std::io::Read + async_std::io::Write + Clone + Send + Sync + Unpin + 'static {}
+
+impl<S> ChatStream for S where S: async_std::io::Read + async_std::io::Write + Clone + Send + Sync + Unpin + 'static {}
+
+//addresses written as "unix:/path/to/socket" mean a unix domain socket, everything else is host:port
+pub fn unix_path(addr: &str) -> Option<&str> {
+    addr.strip_prefix("unix:")
+}
+
 pub async fn send_json<O, P>(leaving: &mut O, packet: &P) -> ChatResult<()>
 where
     O: async_std::io::Write + Unpin,
@@ -0,0 +1,62 @@
+mod support;
+
+use async_std::task;
+use chat_program_study::e2e::{self, RoomKey};
+use chat_program_study::Server;
+use support::{start, test_config, TestClient};
+
+#[test]
+fn the_server_relays_a_sealed_post_unchanged() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let mut alice = TestClient::connect(server.addr()).await;
+        let mut bob = TestClient::connect(server.addr()).await;
+
+        alice.join("secret").await;
+        bob.join("secret").await;
+
+        let sealed = RoomKey::derive("secret", "open sesame").seal("secret", "meet at noon");
+        alice.post("secret", &sealed).await;
+
+        match bob.recv().await {
+            Server::Message { chat_name, message, .. } => {
+                assert_eq!(*chat_name, "secret");
+                assert_eq!(*message, sealed);
+                let key = RoomKey::derive("secret", "open sesame"); //bob derives his own from the same passphrase
+                assert_eq!(key.open("secret", &message).as_deref(), Some("meet at noon"));
+            }
+            other => panic!("expected the sealed post, got {:?}", other),
+        }
+    });
+}
+
+#[test]
+fn a_wrong_passphrase_does_not_open_a_post() {
+    let sealed = RoomKey::derive("secret", "open sesame").seal("secret", "meet at noon");
+
+    assert!(e2e::is_sealed(&sealed));
+    assert_eq!(RoomKey::derive("secret", "open barley").open("secret", &sealed), None);
+}
+
+#[test]
+fn a_post_copied_into_another_room_does_not_open() {
+    let sealed = RoomKey::derive("secret", "open sesame").seal("secret", "meet at noon");
+
+    //same passphrase, but the room name is both the salt and the associated data
+    assert_eq!(RoomKey::derive("other", "open sesame").open("other", &sealed), None);
+    assert_eq!(RoomKey::derive("secret", "open sesame").open("other", &sealed), None);
+}
+
+#[test]
+fn a_tampered_post_does_not_open() {
+    let key = RoomKey::derive("secret", "open sesame");
+    let sealed = key.seal("secret", "meet at noon");
+
+    let mut tampered = sealed.clone().into_bytes();
+    let last = tampered.len() - 3;
+    tampered[last] = if tampered[last] == b'A' { b'B' } else { b'A' };
+    let tampered = String::from_utf8(tampered).unwrap();
+
+    assert_eq!(key.open("secret", &tampered), None);
+    assert!(!e2e::is_sealed("meet at noon"));
+}
@@ -0,0 +1,228 @@
+mod support;
+
+use async_std::task;
+use std::time::{Duration, Instant};
+use chat_program_study::{Client, PresenceState, Server};
+use support::{arc, start, test_config, TestClient, TestConfig, TestServer};
+
+fn federated_config(server_id: &str) -> TestConfig {
+    let mut config = test_config();
+    config.federation.server_id = server_id.to_string();
+    config.federation.secret = "shared secret".to_string();
+    config.federation.shared_rooms = vec!["general".to_string()];
+    config.federation.reconnect_delay_ms = 50;
+    config
+}
+
+async fn wait_for_links(servers: &[&TestServer]) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while servers.iter().any(|server| server.connected_peers() == 0) {
+        assert!(Instant::now() < deadline, "peer links did not come up");
+        task::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+//two servers on ephemeral ports, each dialing the other
+async fn linked_pair() -> (TestServer, TestServer) {
+    let mut london = start(federated_config("london")).await;
+    let mut paris = start(federated_config("paris")).await;
+    london.connect_peer(paris.addr());
+    paris.connect_peer(london.addr());
+    wait_for_links(&[&london, &paris]).await;
+    (london, paris)
+}
+
+#[test]
+fn posts_in_a_shared_room_reach_members_on_the_other_server() {
+    task::block_on(async {
+        let (london, paris) = linked_pair().await;
+        let mut alice = TestClient::connect(london.addr()).await;
+        let mut pierre = TestClient::connect(paris.addr()).await;
+
+        alice.join("general").await;
+        pierre.join("general").await;
+
+        alice.post("general", "hello from london").await;
+        alice.expect_message("general", "hello from london").await;
+        pierre.expect_message("general", "hello from london").await;
+
+        pierre.post("general", "bonjour from paris").await;
+        pierre.expect_message("general", "bonjour from paris").await;
+        alice.expect_message("general", "bonjour from paris").await;
+    });
+}
+
+#[test]
+fn relayed_posts_are_not_sent_back_round_the_loop() {
+    task::block_on(async {
+        let (london, paris) = linked_pair().await;
+        let mut alice = TestClient::connect(london.addr()).await;
+        let mut pierre = TestClient::connect(paris.addr()).await;
+
+        alice.join("general").await;
+        pierre.join("general").await;
+        alice.post("general", "just once").await;
+
+        alice.expect_message("general", "just once").await;
+        pierre.expect_message("general", "just once").await;
+
+        //a second post goes through the same links, if the first one had bounced back it would show up before this
+        alice.post("general", "second").await;
+        alice.expect_message("general", "second").await;
+        pierre.expect_message("general", "second").await;
+        alice.expect_nothing().await;
+        pierre.expect_nothing().await;
+    });
+}
+
+#[test]
+fn rooms_that_are_not_shared_stay_local() {
+    task::block_on(async {
+        let (london, paris) = linked_pair().await;
+        let mut alice = TestClient::connect(london.addr()).await;
+        let mut pierre = TestClient::connect(paris.addr()).await;
+
+        alice.join("london-only").await;
+        pierre.join("london-only").await;
+        alice.post("london-only", "local news").await;
+
+        alice.expect_message("london-only", "local news").await;
+        pierre.expect_nothing().await;
+    });
+}
+
+#[test]
+fn a_peer_that_starts_later_gets_linked_by_the_reconnect() {
+    task::block_on(async {
+        //find a free port for paris, then let london start dialing it before paris is listening
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let paris_addr = format!("127.0.0.1:{}", port);
+
+        let mut london = start(federated_config("london")).await;
+        london.connect_peer(&paris_addr);
+        task::sleep(Duration::from_millis(200)).await; //a few failed attempts
+        assert_eq!(london.connected_peers(), 0);
+
+        let mut paris_config = federated_config("paris");
+        paris_config.listen = vec![paris_addr];
+        let paris = start(paris_config).await;
+        wait_for_links(&[&london]).await;
+
+        let mut pierre = TestClient::connect(paris.addr()).await;
+        let mut alice = TestClient::connect(london.addr()).await;
+        pierre.join("general").await;
+        alice.post("general", "finally").await;
+
+        pierre.expect_message("general", "finally").await;
+    });
+}
+
+#[test]
+fn a_relay_is_refused_without_a_peer_hello() {
+    task::block_on(async {
+        let server = start(federated_config("london")).await;
+        let mut mallory = TestClient::connect(server.addr()).await;
+
+        mallory.send(&Client::Relay {
+            origin: arc("paris"),
+            chat_name: arc("general"),
+            message: arc("spoofed"),
+        }).await;
+
+        mallory.expect_error_containing("PeerHello").await;
+    });
+}
+
+#[test]
+fn a_server_refuses_a_peer_using_its_own_id() {
+    task::block_on(async {
+        let server = start(federated_config("london")).await;
+        let mut twin = TestClient::connect(server.addr()).await;
+
+        twin.send(&Client::PeerHello { server_id: arc("london"), secret: arc("shared secret") }).await;
+
+        twin.expect_error_containing("which is this server").await;
+    });
+}
+
+#[test]
+fn a_peer_hello_without_the_secret_is_refused_and_its_relays_with_it() {
+    task::block_on(async {
+        let server = start(federated_config("london")).await;
+        let mut mallory = TestClient::connect(server.addr()).await;
+        let mut alice = TestClient::connect(server.addr()).await;
+        alice.join("general").await;
+
+        mallory.send(&Client::PeerHello { server_id: arc("paris"), secret: arc("guessed") }).await;
+        mallory.expect_error_containing("wrong secret").await;
+        mallory.send(&Client::Relay { origin: arc("paris"), chat_name: arc("general"), message: arc("forged") }).await;
+        mallory.expect_error_containing("PeerHello").await;
+
+        alice.expect_nothing().await;
+    });
+}
+
+#[test]
+fn a_server_without_a_secret_accepts_no_peers() {
+    task::block_on(async {
+        let mut config = federated_config("london");
+        config.federation.secret = String::new();
+        let server = start(config).await;
+        let mut stranger = TestClient::connect(server.addr()).await;
+
+        stranger.send(&Client::PeerHello { server_id: arc("paris"), secret: arc("") }).await;
+
+        stranger.expect_error_containing("accepts no peers").await;
+    });
+}
+
+#[test]
+fn a_relayed_mention_is_kept_for_an_offline_member() {
+    task::block_on(async {
+        let server = start(federated_config("london")).await;
+        let mut alice = TestClient::connect(server.addr()).await;
+        alice.send(&Client::Login { user_id: arc("alice") }).await;
+        alice.join("general").await;
+        alice.send(&Client::Presence { name: arc("alice"), state: PresenceState::Online }).await;
+        let mut watcher = TestClient::connect(server.addr()).await;
+        watcher.join("general").await;
+        drop(alice);
+        loop {
+            if let Server::Presence { state: PresenceState::Offline, .. } = watcher.recv().await {
+                break;
+            }
+        }
+
+        let mut paris = TestClient::connect(server.addr()).await;
+        paris.send(&Client::PeerHello { server_id: arc("paris"), secret: arc("shared secret") }).await;
+        paris.send(&Client::Relay { origin: arc("paris"), chat_name: arc("general"), message: arc("@alice bonjour") }).await;
+        watcher.expect_message("general", "@alice bonjour").await;
+
+        let mut alice = TestClient::connect(server.addr()).await;
+        alice.send(&Client::Login { user_id: arc("alice") }).await;
+        let delivered = alice.sync().await;
+        match delivered.as_slice() {
+            [Server::Missed { packet, .. }] => match &**packet {
+                Server::Message { message, .. } => assert_eq!(message.as_str(), "@alice bonjour"),
+                other => panic!("expected the mention, got {:?}", other),
+            },
+            other => panic!("expected one missed packet, got {:?}", other),
+        }
+    });
+}
+
+#[test]
+fn a_peer_that_refuses_the_hello_is_never_counted_as_connected() {
+    task::block_on(async {
+        let mut london = start(federated_config("london")).await;
+        let mut paris_config = federated_config("paris");
+        paris_config.federation.secret = "another secret".to_string();
+        let paris = start(paris_config).await;
+
+        london.connect_peer(paris.addr());
+        for _ in 0..10 {
+            assert_eq!(london.connected_peers(), 0);
+            task::sleep(Duration::from_millis(20)).await;
+        }
+    });
+}
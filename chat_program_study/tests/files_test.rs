@@ -0,0 +1,220 @@
+mod support;
+
+use async_std::task;
+use chat_program_study::{Client, FilePacket, PresenceState, Server};
+use support::{arc, start, test_config, TestClient};
+
+// "hello file" in base64, and its sha256
+const DATA: &str = "aGVsbG8gZmlsZQ==";
+const SHA256: &str = "f3877e8a3d98f809d9f844060fbea2864a4b66980a22ff22297014d0c168db2e";
+
+fn offer(id: &str, size: u64) -> FilePacket {
+    FilePacket::Offer { id: arc(id), name: arc("hello.txt"), size, sha256: arc(SHA256) }
+}
+
+fn chunk(id: &str, seq: u64, data: &str) -> FilePacket {
+    FilePacket::Chunk { id: arc(id), seq, data: arc(data) }
+}
+
+async fn send_file(client: &mut TestClient, chat_name: &str, packet: FilePacket) {
+    client.send(&Client::File { chat_name: arc(chat_name), packet }).await;
+}
+
+fn file(chat_name: &str, packet: FilePacket) -> Server {
+    Server::File { chat_name: arc(chat_name), packet }
+}
+
+fn answer(id: &str, refused: Option<&str>) -> Server {
+    Server::FileAnswer { id: arc(id), refused: refused.map(str::to_string) }
+}
+
+async fn expect_refused(client: &mut TestClient, id: &str, text: &str) {
+    match client.recv().await {
+        Server::FileAnswer { id: got, refused: Some(reason) } if *got == id => {
+            assert!(reason.contains(text), "refusal {:?} does not mention {:?}", reason, text)
+        }
+        other => panic!("expected offer {} to be refused, got {:?}", id, other),
+    }
+}
+
+#[test]
+fn a_file_reaches_everyone_in_the_room() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let mut alice = TestClient::connect(server.addr()).await;
+        let mut bob = TestClient::connect(server.addr()).await;
+        alice.join("files").await;
+        bob.join("files").await;
+
+        send_file(&mut alice, "files", offer("f1", 10)).await;
+        let answered = alice.sync().await;
+        assert!(answered.contains(&answer("f1", None)), "got {:?}", answered); //along with our own offer, from the room
+        send_file(&mut alice, "files", chunk("f1", 0, DATA)).await;
+        send_file(&mut alice, "files", FilePacket::Complete { id: arc("f1") }).await;
+
+        bob.expect(file("files", offer("f1", 10))).await;
+        bob.expect(file("files", chunk("f1", 0, DATA))).await;
+        bob.expect(file("files", FilePacket::Complete { id: arc("f1") })).await;
+    });
+}
+
+#[test]
+fn an_offer_over_the_size_limit_is_refused() {
+    task::block_on(async {
+        let mut config = test_config();
+        config.limits.max_file_size = 5;
+        let server = start(config).await;
+        let mut alice = TestClient::connect(server.addr()).await;
+        alice.join("files").await;
+
+        send_file(&mut alice, "files", offer("f1", 10)).await;
+
+        expect_refused(&mut alice, "f1", "the limit is 5").await;
+    });
+}
+
+#[test]
+fn a_chunk_out_of_order_cancels_the_transfer() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let mut alice = TestClient::connect(server.addr()).await;
+        let mut bob = TestClient::connect(server.addr()).await;
+        alice.join("files").await;
+        bob.join("files").await;
+
+        send_file(&mut alice, "files", offer("f1", 10)).await;
+        send_file(&mut alice, "files", chunk("f1", 1, DATA)).await;
+
+        bob.expect(file("files", offer("f1", 10))).await;
+        match bob.recv().await {
+            Server::File { packet: FilePacket::Cancel { id, reason }, .. } => {
+                assert_eq!(*id, "f1");
+                assert!(reason.contains("expected 0"), "unexpected reason {:?}", reason);
+            }
+            other => panic!("expected a cancel, got {:?}", other),
+        }
+    });
+}
+
+#[test]
+fn sending_more_than_was_offered_is_refused() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let mut alice = TestClient::connect(server.addr()).await;
+        let mut bob = TestClient::connect(server.addr()).await;
+        alice.join("files").await;
+        bob.join("files").await;
+
+        send_file(&mut alice, "files", offer("f1", 4)).await;
+        send_file(&mut alice, "files", chunk("f1", 0, DATA)).await;
+
+        bob.expect(file("files", offer("f1", 4))).await;
+        bob.expect(file("files", FilePacket::Cancel { id: arc("f1"), reason: arc("more than the 4 bytes offered") })).await;
+        //the sender's own error and the room's cancel come down different paths, so either can arrive first
+        let error = Server::Error("File f1 cancelled: more than the 4 bytes offered".to_string());
+        assert!(alice.sync().await.contains(&error));
+    });
+}
+
+#[test]
+fn completing_a_file_early_is_refused() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let mut alice = TestClient::connect(server.addr()).await;
+        let mut bob = TestClient::connect(server.addr()).await;
+        alice.join("files").await;
+        bob.join("files").await;
+
+        send_file(&mut alice, "files", offer("f1", 10)).await;
+        send_file(&mut alice, "files", FilePacket::Complete { id: arc("f1") }).await;
+
+        bob.expect(file("files", offer("f1", 10))).await;
+        bob.expect(file("files", FilePacket::Cancel { id: arc("f1"), reason: arc("completed after 0 of 10 bytes") })).await;
+    });
+}
+
+#[test]
+fn a_sender_that_disconnects_cancels_its_transfers() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let mut alice = TestClient::connect(server.addr()).await;
+        let mut bob = TestClient::connect(server.addr()).await;
+        alice.join("files").await;
+        bob.join("files").await;
+
+        send_file(&mut alice, "files", offer("f1", 10)).await;
+        alice.sync().await;
+        drop(alice);
+
+        bob.expect(file("files", offer("f1", 10))).await;
+        bob.expect(file("files", FilePacket::Cancel { id: arc("f1"), reason: arc("the sender disconnected") })).await;
+    });
+}
+
+async fn log_in(addr: &str, user_id: &str) -> TestClient {
+    let mut client = TestClient::connect(addr).await;
+    client.send(&Client::Login { user_id: arc(user_id) }).await;
+    client.sync().await;
+    client
+}
+
+async fn send_direct_file(client: &mut TestClient, to: &str, packet: FilePacket) {
+    client.send(&Client::DirectFile { to: arc(to), packet }).await;
+}
+
+fn direct_file(from: &str, packet: FilePacket) -> Server {
+    Server::DirectFile { from: arc(from), packet }
+}
+
+#[test]
+fn a_file_for_a_user_reaches_every_connection_they_have() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let mut alice = log_in(server.addr(), "alice").await;
+        let mut bob_phone = log_in(server.addr(), "bob").await;
+        let mut bob_laptop = log_in(server.addr(), "bob").await;
+
+        send_direct_file(&mut alice, "bob", offer("f1", 10)).await;
+        send_direct_file(&mut alice, "bob", chunk("f1", 0, DATA)).await;
+        send_direct_file(&mut alice, "bob", FilePacket::Complete { id: arc("f1") }).await;
+
+        for bob in [&mut bob_phone, &mut bob_laptop] {
+            bob.expect(direct_file("alice", offer("f1", 10))).await;
+            bob.expect(direct_file("alice", chunk("f1", 0, DATA))).await;
+            bob.expect(direct_file("alice", FilePacket::Complete { id: arc("f1") })).await;
+        }
+        alice.expect(answer("f1", None)).await;
+        alice.expect_nothing().await;
+    });
+}
+
+#[test]
+fn files_for_users_need_a_login_and_a_connected_receiver() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let mut anonymous = TestClient::connect(server.addr()).await;
+        let mut alice = log_in(server.addr(), "alice").await;
+        let mut bob = log_in(server.addr(), "bob").await;
+        bob.send(&Client::Presence { name: arc("bob"), state: PresenceState::Online }).await;
+        bob.join("__watch__").await;
+        alice.join("__watch__").await;
+
+        send_direct_file(&mut anonymous, "alice", offer("f1", 10)).await;
+        expect_refused(&mut anonymous, "f1", "Log in").await;
+
+        send_direct_file(&mut alice, "carol", offer("f1", 10)).await;
+        expect_refused(&mut alice, "f1", "No such user: carol").await;
+
+        //bob has been seen, but the only connection they had is gone
+        drop(bob);
+        loop {
+            if let Server::Presence { name, state: PresenceState::Offline, .. } = alice.recv().await {
+                if *name == "bob" {
+                    break;
+                }
+            }
+        }
+        send_direct_file(&mut alice, "bob", offer("f2", 10)).await;
+        expect_refused(&mut alice, "f2", "bob is not connected").await;
+    });
+}
@@ -0,0 +1,167 @@
+mod support;
+
+use async_std::task;
+use chat_program_study::{Client, PresenceState, Server};
+use support::{arc, start, test_config, TestClient};
+
+async fn log_in(addr: &str, user_id: &str) -> (TestClient, Vec<Server>) {
+    let mut client = TestClient::connect(addr).await;
+    client.send(&Client::Login { user_id: arc(user_id) }).await;
+    let missed = client.sync().await;
+    (client, missed)
+}
+
+async fn direct(client: &mut TestClient, to: &str, text: &str) {
+    client.send(&Client::Direct { to: arc(to), message: arc(text) }).await;
+}
+
+//disconnects `client` and waits until the server has noticed, which it shows by marking them offline
+async fn disconnect(addr: &str, mut client: TestClient, user_id: &str) {
+    client.send(&Client::Presence { name: arc(user_id), state: PresenceState::Online }).await;
+    client.join("__watch__").await;
+    let mut watcher = TestClient::connect(addr).await;
+    watcher.join("__watch__").await;
+
+    drop(client);
+    loop {
+        if let Server::Presence { name, state: PresenceState::Offline, .. } = watcher.recv().await {
+            if *name == user_id {
+                return;
+            }
+        }
+    }
+}
+
+fn missed(packet: &Server) -> &Server {
+    match packet {
+        Server::Missed { packet, .. } => packet,
+        other => panic!("expected a missed packet, got {:?}", other),
+    }
+}
+
+#[test]
+fn direct_messages_reach_a_connected_user() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let (mut alice, _) = log_in(server.addr(), "alice").await;
+        let (mut bob, _) = log_in(server.addr(), "bob").await;
+
+        direct(&mut alice, "bob", "psst").await;
+
+        bob.expect(Server::Direct { from: arc("alice"), message: arc("psst") }).await;
+        alice.expect_nothing().await;
+    });
+}
+
+#[test]
+fn direct_messages_wait_for_an_offline_user() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let (mut alice, _) = log_in(server.addr(), "alice").await;
+        let (bob, _) = log_in(server.addr(), "bob").await;
+        disconnect(server.addr(), bob, "bob").await;
+
+        direct(&mut alice, "bob", "first").await;
+        direct(&mut alice, "bob", "second").await;
+        alice.expect_nothing().await;
+
+        let (_bob, delivered) = log_in(server.addr(), "bob").await;
+        assert_eq!(delivered.len(), 2, "got {:?}", delivered);
+        assert_eq!(*missed(&delivered[0]), Server::Direct { from: arc("alice"), message: arc("first") });
+        assert_eq!(*missed(&delivered[1]), Server::Direct { from: arc("alice"), message: arc("second") });
+
+        //handed over once, not again on the next login
+        let (_bob, delivered) = log_in(server.addr(), "bob").await;
+        assert_eq!(delivered, Vec::new());
+    });
+}
+
+#[test]
+fn mentions_in_your_rooms_are_kept_while_you_are_away() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let (mut alice, _) = log_in(server.addr(), "alice").await;
+        let (mut bob, _) = log_in(server.addr(), "bob").await;
+        let (carol, _) = log_in(server.addr(), "carol").await;
+        alice.join("rust").await;
+        bob.join("rust").await;
+        disconnect(server.addr(), bob, "bob").await;
+        disconnect(server.addr(), carol, "carol").await; //never joined rust
+
+        alice.post("rust", "@bob, @carol: lunch?").await;
+        alice.post("rust", "no mention here").await;
+        alice.sync().await;
+
+        let (_bob, delivered) = log_in(server.addr(), "bob").await;
+        assert_eq!(delivered.len(), 1, "got {:?}", delivered);
+        match missed(&delivered[0]) {
+            Server::Message { chat_name, message, .. } => assert_eq!((chat_name.as_str(), message.as_str()), ("rust", "@bob, @carol: lunch?")),
+            other => panic!("expected the mention, got {:?}", other),
+        }
+
+        let (_carol, delivered) = log_in(server.addr(), "carol").await;
+        assert_eq!(delivered, Vec::new());
+    });
+}
+
+#[test]
+fn only_the_newest_messages_are_kept() {
+    task::block_on(async {
+        let mut config = test_config();
+        config.offline.max_queued = 2;
+        let server = start(config).await;
+        let (mut alice, _) = log_in(server.addr(), "alice").await;
+        let (bob, _) = log_in(server.addr(), "bob").await;
+        disconnect(server.addr(), bob, "bob").await;
+
+        for text in ["one", "two", "three"] {
+            direct(&mut alice, "bob", text).await;
+        }
+        alice.sync().await;
+
+        let (_bob, delivered) = log_in(server.addr(), "bob").await;
+        let texts: Vec<_> = delivered.iter().map(|packet| match missed(packet) {
+            Server::Direct { message, .. } => message.to_string(),
+            other => panic!("expected a direct message, got {:?}", other),
+        }).collect();
+        assert_eq!(texts, ["two", "three"]);
+    });
+}
+
+#[test]
+fn direct_messages_need_a_login_and_a_known_user() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let mut anonymous = TestClient::connect(server.addr()).await;
+        direct(&mut anonymous, "bob", "hi").await;
+        anonymous.expect_error_containing("Log in before").await;
+
+        let (mut alice, _) = log_in(server.addr(), "alice").await;
+        direct(&mut alice, "nobody", "hi").await;
+        alice.expect_error_containing("No such user: nobody").await;
+
+        alice.send(&Client::Login { user_id: arc("bob") }).await;
+        alice.expect_error_containing("Already logged in as alice").await;
+    });
+}
+
+#[test]
+fn a_user_gone_longer_than_max_age_with_nothing_queued_is_forgotten() {
+    task::block_on(async {
+        let mut config = test_config();
+        config.offline.max_age_secs = 1;
+        let server = start(config).await;
+        let (mut alice, _) = log_in(server.addr(), "alice").await;
+        let (bob, _) = log_in(server.addr(), "bob").await;
+        let (carol, _) = log_in(server.addr(), "carol").await;
+        disconnect(server.addr(), bob, "bob").await;
+
+        task::sleep(std::time::Duration::from_millis(2100)).await;
+        disconnect(server.addr(), carol, "carol").await; //any logout clears out the users that are past it
+
+        direct(&mut alice, "bob", "still there?").await;
+        alice.expect_error_containing("No such user: bob").await;
+        direct(&mut alice, "carol", "still there?").await;
+        alice.expect_nothing().await;
+    });
+}
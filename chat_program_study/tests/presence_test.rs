@@ -0,0 +1,131 @@
+mod support;
+
+use async_std::task;
+use chat_program_study::{Client, PresenceState, Server};
+use support::{arc, start, test_config, TestClient};
+
+async fn presence(client: &mut TestClient, name: &str, state: PresenceState) {
+    client.send(&Client::Presence { name: arc(name), state }).await;
+}
+
+async fn typing(client: &mut TestClient, chat_name: &str) {
+    client.send(&Client::Typing { chat_name: arc(chat_name) }).await;
+}
+
+fn shown(chat_name: &str, name: &str, state: PresenceState) -> Server {
+    Server::Presence { chat_name: arc(chat_name), name: arc(name), state }
+}
+
+#[test]
+fn members_see_each_other_come_and_go() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let mut alice = TestClient::connect(server.addr()).await;
+        let mut bob = TestClient::connect(server.addr()).await;
+        let mut carol = TestClient::connect(server.addr()).await;
+        alice.join("rust").await;
+        bob.join("rust").await;
+
+        presence(&mut alice, "alice", PresenceState::Online).await;
+        alice.expect(shown("rust", "alice", PresenceState::Online)).await;
+        bob.expect(shown("rust", "alice", PresenceState::Online)).await;
+
+        presence(&mut carol, "carol", PresenceState::Online).await;
+        let on_joining = carol.join("rust").await;
+        assert!(on_joining.contains(&shown("rust", "alice", PresenceState::Online)), "got {:?}", on_joining); //who was already there
+        alice.expect(shown("rust", "carol", PresenceState::Online)).await;
+
+        presence(&mut alice, "alice", PresenceState::Away).await;
+        bob.expect(shown("rust", "carol", PresenceState::Online)).await;
+        bob.expect(shown("rust", "alice", PresenceState::Away)).await;
+
+        drop(carol);
+        alice.expect(shown("rust", "alice", PresenceState::Away)).await;
+        alice.expect(shown("rust", "carol", PresenceState::Offline)).await;
+    });
+}
+
+#[test]
+fn repeating_the_same_state_is_not_broadcast() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let mut alice = TestClient::connect(server.addr()).await;
+        let mut bob = TestClient::connect(server.addr()).await;
+        bob.join("rust").await;
+
+        presence(&mut alice, "alice", PresenceState::Online).await;
+        alice.join("rust").await;
+        for _ in 0..10 {
+            presence(&mut alice, "alice", PresenceState::Online).await;
+        }
+        alice.sync().await;
+
+        bob.expect(shown("rust", "alice", PresenceState::Online)).await;
+        bob.expect_nothing().await;
+    });
+}
+
+#[test]
+fn typing_notices_are_coalesced() {
+    task::block_on(async {
+        let server = start(test_config()).await; //typing_interval_ms is several seconds
+        let mut alice = TestClient::connect(server.addr()).await;
+        let mut bob = TestClient::connect(server.addr()).await;
+        bob.join("rust").await;
+
+        presence(&mut alice, "alice", PresenceState::Online).await;
+        alice.join("rust").await;
+        for _ in 0..100 {
+            typing(&mut alice, "rust").await;
+        }
+        alice.sync().await;
+
+        bob.expect(shown("rust", "alice", PresenceState::Online)).await;
+        bob.expect(Server::Typing { chat_name: arc("rust"), name: arc("alice") }).await;
+        bob.expect_nothing().await;
+    });
+}
+
+#[test]
+fn typing_needs_a_name_and_a_room() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let mut alice = TestClient::connect(server.addr()).await;
+        alice.join("rust").await;
+
+        typing(&mut alice, "rust").await;
+        alice.expect_error_containing("before Typing").await;
+
+        let mut dave = TestClient::connect(server.addr()).await;
+        presence(&mut dave, "dave", PresenceState::Online).await;
+        typing(&mut dave, "rust").await;
+        dave.expect_error_containing("Join rust before typing").await;
+        typing(&mut dave, "go").await;
+        dave.expect_error_containing("Chat does not exist: go").await;
+    });
+}
+
+#[test]
+fn logging_in_decides_the_presence_name() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let mut alice = TestClient::connect(server.addr()).await;
+        let mut bob = TestClient::connect(server.addr()).await;
+        alice.join("rust").await;
+        bob.join("rust").await;
+
+        presence(&mut alice, "bob", PresenceState::Online).await;
+        bob.expect(shown("rust", "bob", PresenceState::Online)).await;
+        alice.send(&Client::Login { user_id: arc("alice") }).await;
+        bob.expect(shown("rust", "bob", PresenceState::Offline)).await;
+        bob.expect(shown("rust", "alice", PresenceState::Online)).await;
+
+        presence(&mut alice, "bob", PresenceState::Away).await;
+        let refused = alice.sync().await.into_iter().any(|packet| matches!(packet, Server::Error(error) if error.contains("Logged in as alice")));
+        assert!(refused, "presence was shown as bob after logging in as alice");
+        bob.expect_nothing().await;
+
+        presence(&mut alice, "alice", PresenceState::Away).await;
+        bob.expect(shown("rust", "alice", PresenceState::Away)).await;
+    });
+}
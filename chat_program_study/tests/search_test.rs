@@ -0,0 +1,138 @@
+mod support;
+
+use async_std::task;
+use chat_program_study::{Client, Server};
+use support::{arc, start, test_config, TestClient};
+
+//the messages a Search found, newest first. posts the client sees live on the way are skipped
+async fn search(client: &mut TestClient, chat_name: &str, query: &str, limit: usize) -> Vec<String> {
+    client.send(&Client::Search { chat_name: arc(chat_name), query: arc(query), limit }).await;
+    loop {
+        match client.recv().await {
+            Server::Found { results, .. } => return results.into_iter().map(|found| found.message.to_string()).collect(),
+            Server::Message { .. } => continue,
+            other => panic!("expected search results, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn search_finds_posts_containing_every_word() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let mut alice = TestClient::connect(server.addr()).await;
+        let mut bob = TestClient::connect(server.addr()).await;
+        bob.join("rust").await;
+
+        bob.post("rust", "The borrow checker is fine").await;
+        bob.post("rust", "lifetimes and the borrow checker").await;
+        bob.post("rust", "nothing to see here").await;
+        bob.post("go", "borrow checker? no such room").await; //never joined, so it was never posted
+
+        //only members can read a room's history
+        alice.send(&Client::Search { chat_name: arc("rust"), query: arc("borrow"), limit: 10 }).await;
+        alice.expect_error_containing("Join rust before searching it").await;
+        alice.join("rust").await;
+
+        assert_eq!(search(&mut alice, "rust", "BORROW checker", 10).await, [
+            "lifetimes and the borrow checker",
+            "The borrow checker is fine",
+        ]);
+        assert_eq!(search(&mut alice, "rust", "borrow lifetimes", 10).await, ["lifetimes and the borrow checker"]);
+        assert_eq!(search(&mut alice, "rust", "goroutines", 10).await, Vec::<String>::new());
+    });
+}
+
+#[test]
+fn search_returns_at_most_limit_results() {
+    task::block_on(async {
+        let mut config = test_config();
+        config.limits.max_search_results = 3;
+        let server = start(config).await;
+        let mut alice = TestClient::connect(server.addr()).await;
+        alice.join("counting").await;
+
+        for n in 0..6 {
+            alice.post("counting", &format!("number {}", n)).await;
+        }
+        alice.sync().await;
+
+        assert_eq!(search(&mut alice, "counting", "number", 2).await, ["number 5", "number 4"]);
+        assert_eq!(search(&mut alice, "counting", "number", 50).await.len(), 3); //capped by the server
+    });
+}
+
+#[test]
+fn history_outlives_the_server() {
+    task::block_on(async {
+        let config = test_config();
+        let first = start(config.clone()).await;
+        let mut alice = TestClient::connect(first.addr()).await;
+        alice.join("c++ & rust").await;
+        alice.post("c++ & rust", "remember this").await;
+        assert_eq!(search(&mut alice, "c++ & rust", "remember", 10).await, ["remember this"]);
+        first.shutdown().await;
+
+        let second = start(config).await;
+        let mut bob = TestClient::connect(second.addr()).await;
+        bob.join("c++ & rust").await;
+        assert_eq!(search(&mut bob, "c++ & rust", "remember", 10).await, ["remember this"]);
+    });
+}
+
+#[test]
+fn a_search_needs_something_to_look_for() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let mut alice = TestClient::connect(server.addr()).await;
+        alice.join("rust").await;
+
+        alice.send(&Client::Search { chat_name: arc("rust"), query: arc("  ?! "), limit: 10 }).await;
+
+        alice.expect_error_containing("at least one word").await;
+    });
+}
+
+#[test]
+fn a_posting_cut_short_by_a_crash_is_dropped() {
+    task::block_on(async {
+        let config = test_config();
+        let first = start(config.clone()).await;
+        let mut alice = TestClient::connect(first.addr()).await;
+        alice.join("rust").await;
+        alice.post("rust", "written whole").await;
+        alice.sync().await;
+        first.shutdown().await;
+
+        let postings = config.data_dir.join("history").join("rust").join("postings");
+        let mut cut_short = std::fs::read(&postings).unwrap();
+        cut_short.extend_from_slice(&[7; 13]); //half a record
+        std::fs::write(&postings, cut_short).unwrap();
+
+        let second = start(config.clone()).await;
+        let mut bob = TestClient::connect(second.addr()).await;
+        bob.join("rust").await;
+        bob.post("rust", "written after the crash").await;
+        bob.sync().await;
+        second.shutdown().await;
+
+        //the posting made after the crash has a line of its own, so it is still there next time
+        let third = start(config).await;
+        let mut bob = TestClient::connect(third.addr()).await;
+        bob.join("rust").await;
+        assert_eq!(search(&mut bob, "rust", "written", 10).await, ["written after the crash", "written whole"]);
+    });
+}
+
+#[test]
+fn searching_a_room_without_history_creates_nothing() {
+    task::block_on(async {
+        let config = test_config();
+        let server = start(config.clone()).await;
+        let mut alice = TestClient::connect(server.addr()).await;
+        alice.join("quiet").await;
+
+        assert_eq!(search(&mut alice, "quiet", "anything", 10).await, Vec::<String>::new());
+        assert!(!config.data_dir.join("history").join("quiet").exists());
+    });
+}
@@ -0,0 +1,133 @@
+mod support;
+
+use async_std::task;
+use chat_program_study::server::start_server;
+use chat_program_study::Server;
+use support::{start, test_config, TestClient};
+
+#[test]
+fn members_of_a_room_receive_each_others_posts() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let mut alice = TestClient::connect(server.addr()).await;
+        let mut bob = TestClient::connect(server.addr()).await;
+
+        alice.join("rust").await;
+        bob.join("rust").await;
+        bob.post("rust", "hello alice").await;
+
+        alice.expect_message("rust", "hello alice").await;
+        bob.expect_message("rust", "hello alice").await; //the poster is a member too
+    });
+}
+
+#[test]
+fn posts_stay_in_their_room() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let mut alice = TestClient::connect(server.addr()).await;
+        let mut carol = TestClient::connect(server.addr()).await;
+
+        alice.join("rust").await;
+        carol.join("go").await;
+        alice.post("rust", "only for rust").await;
+
+        alice.expect_message("rust", "only for rust").await;
+        carol.expect_nothing().await;
+    });
+}
+
+#[test]
+fn posting_to_an_unknown_room_is_an_error() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let mut alice = TestClient::connect(server.addr()).await;
+
+        alice.post("nowhere", "anyone?").await;
+
+        alice.expect_error_containing("Chat does not exist: nowhere").await;
+    });
+}
+
+#[test]
+fn oversized_messages_are_rejected() {
+    task::block_on(async {
+        let mut config = test_config();
+        config.limits.max_message_len = 8;
+        let server = start(config).await;
+        let mut alice = TestClient::connect(server.addr()).await;
+
+        alice.join("rust").await;
+        alice.post("rust", "far too long for the limit").await;
+
+        alice.expect_error_containing("longer than 8 bytes").await;
+    });
+}
+
+#[test]
+fn a_member_that_falls_behind_is_told_how_many_messages_it_lost() {
+    task::block_on(async {
+        let mut config = test_config();
+        config.room_capacity = 1;
+        config.limits.max_message_len = 1 << 20;
+        let server = start(config).await;
+        let mut slow = TestClient::connect(server.addr()).await;
+        let mut poster = TestClient::connect(server.addr()).await;
+
+        slow.join("busy").await;
+
+        //far more than the socket buffers hold, so the server gets stuck writing to `slow`
+        //while the room moves on without it
+        let big = "x".repeat(512 * 1024);
+        for _ in 0..64 {
+            poster.post("busy", &big).await;
+        }
+        poster.sync().await;
+
+        for _ in 0..64 {
+            match slow.recv().await {
+                Server::Error(error) => {
+                    assert!(error.starts_with("Dropped "), "unexpected error {:?}", error);
+                    assert!(error.ends_with("from busy."), "unexpected error {:?}", error);
+                    return;
+                }
+                _ => continue,
+            }
+        }
+        panic!("the slow member was never told about the dropped messages");
+    });
+}
+
+#[test]
+fn the_server_reports_its_ephemeral_port() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+
+        assert!(server.addr().starts_with("127.0.0.1:"));
+        assert_ne!(server.addr(), "127.0.0.1:0");
+    });
+}
+
+#[test]
+fn an_invalid_config_is_refused() {
+    task::block_on(async {
+        let mut config = test_config();
+        config.room_capacity = 0;
+
+        let error = start_server(config.config.clone()).await.err().expect("the server should not start");
+
+        assert!(error.to_string().contains("room_capacity must be at least 1"));
+    });
+}
+
+#[test]
+fn shutdown_stops_accepting_connections() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let addr = server.addr().to_string();
+
+        server.shutdown().await;
+
+        assert!(async_std::net::TcpStream::connect(addr).await.is_err());
+    });
+}
@@ -0,0 +1,182 @@
+// helpers shared by the integration tests: a server started in-process on an ephemeral port
+// and a small client that talks the real protocol and asserts on what comes back
+#![allow(dead_code)] //not every test file uses every helper
+
+use async_std::prelude::*;
+use async_std::{future, io, net};
+use std::ops::{Deref, DerefMut};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chat_program_study::server::config::{LogLevel, Logging, ServerConfig};
+use chat_program_study::server::{start_server, ServerHandle};
+use chat_program_study::utils::{self, ChatResult};
+use chat_program_study::{Client, Server};
+
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+//a room name no test ever creates, posting to it is how sync() finds out the server caught up
+const SYNC_ROOM: &str = "__sync__";
+
+//a server config whose data directory is removed once the last copy of it, and every server started
+//from one, has been dropped. clones share the directory, so a test can restart a server on the same data
+#[derive(Clone)]
+pub struct TestConfig {
+    pub config: ServerConfig,
+    _data_dir: Arc<DataDir>,
+}
+
+struct DataDir(PathBuf);
+
+impl Drop for DataDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+impl Deref for TestConfig {
+    type Target = ServerConfig;
+    fn deref(&self) -> &ServerConfig {
+        &self.config
+    }
+}
+
+impl DerefMut for TestConfig {
+    fn deref_mut(&mut self) -> &mut ServerConfig {
+        &mut self.config
+    }
+}
+
+pub fn test_config() -> TestConfig {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let data_dir = format!("chat-test-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::SeqCst));
+    let data_dir = std::env::temp_dir().join(data_dir);
+
+    TestConfig {
+        config: ServerConfig {
+            listen: vec!["127.0.0.1:0".to_string()],
+            data_dir: data_dir.clone(),
+            logging: Logging { level: LogLevel::Error, ..Logging::default() },
+            ..ServerConfig::default()
+        },
+        _data_dir: Arc::new(DataDir(data_dir)),
+    }
+}
+
+//a running server that keeps its data directory around until it has been dropped
+pub struct TestServer {
+    handle: ServerHandle,
+    _data_dir: Arc<DataDir>,
+}
+
+impl TestServer {
+    pub async fn shutdown(self) {
+        self.handle.shutdown().await
+    }
+}
+
+impl Deref for TestServer {
+    type Target = ServerHandle;
+    fn deref(&self) -> &ServerHandle {
+        &self.handle
+    }
+}
+
+impl DerefMut for TestServer {
+    fn deref_mut(&mut self) -> &mut ServerHandle {
+        &mut self.handle
+    }
+}
+
+pub async fn start(config: TestConfig) -> TestServer {
+    let handle = start_server(config.config).await.expect("server failed to start");
+    TestServer { handle, _data_dir: config._data_dir }
+}
+
+pub fn arc(text: &str) -> Arc<String> {
+    Arc::new(text.to_string())
+}
+
+
+pub struct TestClient {
+    to_server: net::TcpStream,
+    from_server: Pin<Box<dyn Stream<Item = ChatResult<Server>> + Send>>,
+}
+
+impl TestClient {
+    pub async fn connect(addr: &str) -> TestClient {
+        let socket = net::TcpStream::connect(addr).await.expect("cannot connect to the test server");
+        socket.set_nodelay(true).unwrap();
+
+        TestClient {
+            to_server: socket.clone(),
+            from_server: Box::pin(utils::receive(io::BufReader::new(socket))),
+        }
+    }
+
+    pub async fn send(&mut self, request: &Client) {
+        utils::send_json(&mut self.to_server, request).await.unwrap();
+        self.to_server.flush().await.unwrap();
+    }
+
+    //joins and waits until the server has actually subscribed us, so a post made right after is seen.
+    //returns what arrived in the meantime, e.g. who is already in the room
+    pub async fn join(&mut self, chat_name: &str) -> Vec<Server> {
+        self.send(&Client::Join { chat_name: arc(chat_name) }).await;
+        self.sync().await
+    }
+
+    pub async fn post(&mut self, chat_name: &str, message: &str) {
+        self.send(&Client::Post { chat_name: arc(chat_name), message: arc(message) }).await;
+    }
+
+    //the server handles a connection's requests in order, so once the answer to this one comes back
+    //everything sent before it has been dealt with. any packets that arrive first are returned.
+    pub async fn sync(&mut self) -> Vec<Server> {
+        self.post(SYNC_ROOM, "").await;
+
+        let mut before = Vec::new();
+        loop {
+            match self.recv().await {
+                Server::Error(error) if error.contains(SYNC_ROOM) => return before,
+                packet => before.push(packet),
+            }
+        }
+    }
+
+    pub async fn recv(&mut self) -> Server {
+        match future::timeout(RECEIVE_TIMEOUT, self.from_server.next()).await {
+            Ok(Some(packet)) => packet.expect("bad packet from the server"),
+            Ok(None) => panic!("the server closed the connection"),
+            Err(_) => panic!("nothing received within {:?}", RECEIVE_TIMEOUT),
+        }
+    }
+
+    pub async fn expect(&mut self, expected: Server) {
+        assert_eq!(self.recv().await, expected);
+    }
+
+    //a message in `chat_name` saying `text`, whatever id the server gave it, which is returned
+    pub async fn expect_message(&mut self, chat_name: &str, text: &str) -> u64 {
+        match self.recv().await {
+            Server::Message { chat_name: got, id, message, .. } if *got == chat_name && *message == text => id,
+            other => panic!("expected {:?} in {}, got {:?}", text, chat_name, other),
+        }
+    }
+
+    pub async fn expect_error_containing(&mut self, text: &str) {
+        match self.recv().await {
+            Server::Error(error) => assert!(error.contains(text), "error {:?} does not mention {:?}", error, text),
+            other => panic!("expected an error mentioning {:?}, got {:?}", text, other),
+        }
+    }
+
+    //nothing but the sync reply turns up
+    pub async fn expect_nothing(&mut self) {
+        assert_eq!(self.sync().await, Vec::new());
+    }
+}
@@ -0,0 +1,127 @@
+mod support;
+
+use async_std::task;
+use chat_program_study::{Client, Server};
+use std::collections::BTreeMap;
+use support::{arc, start, test_config, TestClient};
+
+async fn log_in(addr: &str, user_id: &str) -> TestClient {
+    let mut client = TestClient::connect(addr).await;
+    client.send(&Client::Login { user_id: arc(user_id) }).await;
+    client.sync().await;
+    client
+}
+
+async fn react(client: &mut TestClient, chat_name: &str, message_id: u64, emoji: &str, add: bool) {
+    let (chat_name, emoji) = (arc(chat_name), arc(emoji));
+    let request = match add {
+        true => Client::React { chat_name, message_id, emoji },
+        false => Client::Unreact { chat_name, message_id, emoji },
+    };
+    client.send(&request).await;
+}
+
+fn reactions(chat_name: &str, message_id: u64, counts: &[(&str, usize)]) -> Server {
+    let counts: BTreeMap<_, _> = counts.iter().map(|(emoji, count)| (arc(emoji), *count)).collect();
+    Server::Reactions { chat_name: arc(chat_name), message_id, counts }
+}
+
+#[test]
+fn replies_point_at_the_message_they_answer() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let mut alice = TestClient::connect(server.addr()).await;
+        alice.join("rust").await;
+
+        alice.post("rust", "question").await;
+        let question = alice.expect_message("rust", "question").await;
+        alice.post("rust", "unrelated").await;
+        let unrelated = alice.expect_message("rust", "unrelated").await;
+        assert!(unrelated > question);
+
+        alice.send(&Client::Reply { chat_name: arc("rust"), reply_to: question, message: arc("answer") }).await;
+        match alice.recv().await {
+            Server::Message { reply_to, message, id, .. } => {
+                assert_eq!((reply_to, message.as_str()), (Some(question), "answer"));
+                assert!(id > unrelated);
+            }
+            other => panic!("expected the reply, got {:?}", other),
+        }
+    });
+}
+
+#[test]
+fn a_reply_needs_a_message_to_answer() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let mut alice = TestClient::connect(server.addr()).await;
+        alice.join("rust").await;
+
+        alice.send(&Client::Reply { chat_name: arc("rust"), reply_to: 42, message: arc("to what?") }).await;
+
+        alice.expect_error_containing("No message 42 in rust").await;
+    });
+}
+
+#[test]
+fn reactions_are_counted_once_per_user() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let mut alice = log_in(server.addr(), "alice").await;
+        let mut bob = log_in(server.addr(), "bob").await;
+        alice.join("rust").await;
+        bob.join("rust").await;
+
+        alice.post("rust", "shipped it").await;
+        let id = alice.expect_message("rust", "shipped it").await;
+        bob.expect_message("rust", "shipped it").await;
+
+        react(&mut alice, "rust", id, "🎉", true).await;
+        bob.expect(reactions("rust", id, &[("🎉", 1)])).await;
+        react(&mut bob, "rust", id, "🎉", true).await;
+        bob.expect(reactions("rust", id, &[("🎉", 2)])).await;
+        react(&mut bob, "rust", id, "🎉", true).await; //again, changes nothing so nothing is sent
+        react(&mut bob, "rust", id, "👀", true).await;
+        bob.expect(reactions("rust", id, &[("👀", 1), ("🎉", 2)])).await;
+        react(&mut alice, "rust", id, "🎉", false).await;
+        bob.expect(reactions("rust", id, &[("👀", 1), ("🎉", 1)])).await;
+    });
+}
+
+#[test]
+fn reacting_needs_a_login_and_a_real_message() {
+    task::block_on(async {
+        let server = start(test_config()).await;
+        let mut anonymous = TestClient::connect(server.addr()).await;
+        anonymous.join("rust").await;
+        react(&mut anonymous, "rust", 1, "👍", true).await;
+        anonymous.expect_error_containing("Log in before reacting").await;
+
+        let mut alice = log_in(server.addr(), "alice").await;
+        alice.join("rust").await;
+        react(&mut alice, "rust", 1, "👍", true).await;
+        alice.expect_error_containing("No message 1 in rust").await;
+    });
+}
+
+#[test]
+fn only_the_newest_messages_can_be_reacted_to() {
+    task::block_on(async {
+        let mut config = test_config();
+        config.limits.max_reacted_messages = 2;
+        let server = start(config).await;
+        let mut alice = log_in(server.addr(), "alice").await;
+        alice.join("rust").await;
+
+        let mut ids = Vec::new();
+        for text in ["one", "two", "three"] {
+            alice.post("rust", text).await;
+            ids.push(alice.expect_message("rust", text).await);
+        }
+
+        react(&mut alice, "rust", ids[0], "👍", true).await;
+        alice.expect_error_containing("too old to react to").await;
+        react(&mut alice, "rust", ids[1], "👍", true).await;
+        alice.expect(reactions("rust", ids[1], &[("👍", 1)])).await;
+    });
+}
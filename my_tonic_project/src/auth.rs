@@ -0,0 +1,88 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+// checks the authorization header the client's MyInterceptor sends, "Bearer TOKEN" with TOKEN in the allow-list
+// given to the mock server (--token). it guards service.Controller and service.Admin, health and reflection stay open.
+// an empty allow-list lets everyone in, which is how the mock server behaved before it had one.
+
+#[derive(Clone, Debug, Default)]
+pub struct AuthInterceptor {
+    tokens: Arc<HashSet<String>>,
+}
+
+impl AuthInterceptor {
+    pub fn new<I: IntoIterator<Item = String>>(tokens: I) -> AuthInterceptor {
+        AuthInterceptor { tokens: Arc::new(tokens.into_iter().collect()) }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.tokens.is_empty()
+    }
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        if self.is_open() {
+            return Ok(request);
+        }
+
+        let header = request.metadata().get("authorization")
+            .ok_or_else(|| Status::unauthenticated("missing authorization header"))?;
+        let token = header.to_str().ok()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("the authorization header should be \"Bearer TOKEN\""))?;
+
+        match self.tokens.contains(token.trim()) {
+            true => Ok(request),
+            false => Err(Status::unauthenticated("unknown token")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::Code;
+
+    fn request(authorization: Option<&str>) -> Request<()> {
+        let mut request = Request::new(());
+        if let Some(value) = authorization {
+            request.metadata_mut().insert("authorization", value.parse().unwrap());
+        }
+        request
+    }
+
+    fn check(interceptor: &mut AuthInterceptor, authorization: Option<&str>) -> Result<(), (Code, String)> {
+        interceptor.call(request(authorization))
+            .map(|_| ())
+            .map_err(|status| (status.code(), status.message().to_string()))
+    }
+
+    #[test]
+    fn a_listed_token_is_let_in() {
+        let mut auth = AuthInterceptor::new(["MYTOKEN".to_string(), "OTHER".to_string()]);
+
+        assert!(check(&mut auth, Some("Bearer MYTOKEN")).is_ok());
+        assert!(check(&mut auth, Some("Bearer OTHER")).is_ok());
+    }
+
+    #[test]
+    fn anything_else_is_unauthenticated() {
+        let mut auth = AuthInterceptor::new(["MYTOKEN".to_string()]);
+
+        assert_eq!(check(&mut auth, None), Err((Code::Unauthenticated, "missing authorization header".to_string())));
+        assert_eq!(check(&mut auth, Some("Bearer NOPE")), Err((Code::Unauthenticated, "unknown token".to_string())));
+        assert_eq!(check(&mut auth, Some("MYTOKEN")).unwrap_err().0, Code::Unauthenticated);
+        assert_eq!(check(&mut auth, Some("Basic MYTOKEN")).unwrap_err().0, Code::Unauthenticated);
+    }
+
+    #[test]
+    fn without_an_allow_list_everyone_is_let_in() {
+        let mut auth = AuthInterceptor::default();
+
+        assert!(auth.is_open());
+        assert!(check(&mut auth, None).is_ok());
+    }
+}
@@ -0,0 +1,235 @@
+use serde::Serialize;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use seank_tonic_project_rs::session::{SessionError, Step};
+
+// what --sessions N keeps of every session, and the summary the client prints (or writes as JSON with --report)
+// once all of them are over. a session that failed (couldn't connect, broken stream, ...) counts as a failure
+// like one that ended with SimOver { success: false }, batch mode doesn't retry.
+
+// follows one session as its steps come in
+#[derive(Debug)]
+pub struct Tracker {
+    started: Instant,
+    goal: Option<(f32, f32)>,
+    reached: Option<Duration>, //when the point first got inside the goal area
+    overshoot: f32,
+    sent: Arc<AtomicU64>, //counted by the outbound stream, see sent()
+}
+
+impl Default for Tracker {
+    fn default() -> Self {
+        Tracker { started: Instant::now(), goal: None, reached: None, overshoot: 0.0, sent: Arc::default() }
+    }
+}
+
+impl Tracker {
+    //the counter the outbound stream adds one to for every control value it sends
+    pub fn sent(&self) -> Arc<AtomicU64> {
+        self.sent.clone()
+    }
+
+    pub fn on_step(&mut self, step: &Step) {
+        let x = match step {
+            Step::Started(instruction, state) => {
+                self.goal = Some((instruction.g_minimum_x, instruction.g_maximum_x));
+                state.x
+            }
+            Step::Moved(state) => state.x,
+            _ => return,
+        };
+        let Some((min, max)) = self.goal else { return };
+
+        match self.reached {
+            None if (min..=max).contains(&x) => self.reached = Some(self.started.elapsed()),
+            None => {}
+            //the furthest the point strayed out of the goal area after getting there
+            Some(_) => self.overshoot = self.overshoot.max(min - x).max(x - max),
+        }
+    }
+
+    pub fn finish(self, session: usize, result: &Result<bool, SessionError>) -> SessionStats {
+        SessionStats {
+            session,
+            success: matches!(result, Ok(true)),
+            error: result.as_ref().err().map(ToString::to_string),
+            duration_ms: self.started.elapsed().as_millis() as u64,
+            time_to_goal_ms: self.reached.map(|reached| reached.as_millis() as u64),
+            overshoot: self.overshoot,
+            control_messages: self.sent.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SessionStats {
+    pub session: usize, //from 1, in the order they were started
+    pub success: bool,
+    pub error: Option<String>, //why it didn't run to a SimOver, when it didn't
+    pub duration_ms: u64,
+    pub time_to_goal_ms: Option<u64>, //none when the point never got inside the goal area
+    pub overshoot: f32,
+    pub control_messages: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Spread {
+    pub min: f64,
+    pub mean: f64,
+    pub max: f64,
+}
+
+impl Spread {
+    fn of(values: impl IntoIterator<Item = f64>) -> Option<Spread> {
+        let values: Vec<f64> = values.into_iter().collect();
+        if values.is_empty() {
+            return None;
+        }
+        Some(Spread {
+            min: values.iter().copied().fold(f64::INFINITY, f64::min),
+            mean: values.iter().sum::<f64>() / values.len() as f64,
+            max: values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Summary {
+    pub sessions: usize,
+    pub succeeded: usize,
+    pub success_rate: f64,
+    pub duration_ms: Option<Spread>,
+    pub time_to_goal_ms: Option<Spread>, //over the sessions that got there
+    pub overshoot: Option<Spread>, //likewise
+    pub control_messages: Option<Spread>,
+    pub runs: Vec<SessionStats>,
+}
+
+impl Summary {
+    pub fn new(mut runs: Vec<SessionStats>) -> Summary {
+        runs.sort_by_key(|run| run.session);
+        let succeeded = runs.iter().filter(|run| run.success).count();
+        let reached = || runs.iter().filter(|run| run.time_to_goal_ms.is_some());
+
+        Summary {
+            sessions: runs.len(),
+            succeeded,
+            success_rate: if runs.is_empty() { 0.0 } else { succeeded as f64 / runs.len() as f64 },
+            duration_ms: Spread::of(runs.iter().map(|run| run.duration_ms as f64)),
+            time_to_goal_ms: Spread::of(reached().filter_map(|run| run.time_to_goal_ms).map(|ms| ms as f64)),
+            overshoot: Spread::of(reached().map(|run| run.overshoot as f64)),
+            control_messages: Spread::of(runs.iter().map(|run| run.control_messages as f64)),
+            runs,
+        }
+    }
+
+    pub fn all_succeeded(&self) -> bool {
+        self.succeeded == self.sessions
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for run in &self.runs {
+            let outcome = match (&run.error, run.success) {
+                (Some(error), _) => format!("error: {}", error),
+                (None, true) => "success".to_string(),
+                (None, false) => "failure".to_string(),
+            };
+            let to_goal = run.time_to_goal_ms.map_or("-".to_string(), |ms| format!("{:.2}s", ms as f64 / 1000.0));
+            writeln!(f, "#{:<3} {:>7.2}s  goal after {:>6}  overshoot {:>6.2}  {:>5} controls  {}",
+                     run.session, run.duration_ms as f64 / 1000.0, to_goal, run.overshoot, run.control_messages, outcome)?;
+        }
+
+        writeln!(f, "{} of {} sessions succeeded ({:.0}%)", self.succeeded, self.sessions, self.success_rate * 100.0)?;
+        let spreads = [
+            ("duration (s)", &self.duration_ms, 0.001, 2),
+            ("time to goal (s)", &self.time_to_goal_ms, 0.001, 2),
+            ("overshoot", &self.overshoot, 1.0, 2),
+            ("control messages", &self.control_messages, 1.0, 0),
+        ];
+        for (name, spread, scale, digits) in spreads {
+            if let Some(spread) = spread {
+                writeln!(f, "{:<17} min {:.*}  mean {:.*}  max {:.*}", name,
+                         digits, spread.min * scale, digits, spread.mean * scale, digits, spread.max * scale)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use seank_tonic_project_rs::control::{ServiceInstruction, ServiceState};
+
+    fn started() -> Step {
+        let instruction = ServiceInstruction { b_minimum_x: -50.0, b_maximum_x: 50.0, g_minimum_x: -26.0, g_maximum_x: -24.0 };
+        Step::Started(instruction, ServiceState { x: 0.0 })
+    }
+
+    fn moved(x: f32) -> Step {
+        Step::Moved(ServiceState { x })
+    }
+
+    fn run(session: usize, success: bool, time_to_goal_ms: Option<u64>, overshoot: f32) -> SessionStats {
+        SessionStats { session, success, error: None, duration_ms: 10_000, time_to_goal_ms, overshoot, control_messages: 500 }
+    }
+
+    #[test]
+    fn overshoot_only_counts_after_reaching_the_goal() {
+        let mut tracker = Tracker::default();
+        for step in [started(), moved(-10.0), moved(-24.5), moved(-27.5), moved(-25.0), moved(-23.0)] {
+            tracker.on_step(&step);
+        }
+        tracker.sent().fetch_add(6, Ordering::Relaxed);
+
+        let stats = tracker.finish(1, &Ok(true));
+
+        assert!(stats.success && stats.error.is_none());
+        assert!(stats.time_to_goal_ms.is_some());
+        assert_eq!(stats.overshoot, 1.5);
+        assert_eq!(stats.control_messages, 6);
+    }
+
+    #[test]
+    fn a_session_that_never_got_there_has_no_time_to_goal() {
+        let mut tracker = Tracker::default();
+        for step in [started(), moved(20.0), moved(51.0)] {
+            tracker.on_step(&step);
+        }
+
+        let stats = tracker.finish(1, &Err(SessionError::ClosedEarly));
+
+        assert!(!stats.success);
+        assert_eq!(stats.error.as_deref(), Some("the server closed the stream before the simulation was over"));
+        assert_eq!((stats.time_to_goal_ms, stats.overshoot), (None, 0.0));
+    }
+
+    #[test]
+    fn the_summary_spreads_over_the_sessions() {
+        let summary = Summary::new(vec![run(2, false, None, 0.0), run(1, true, Some(8_000), 1.0), run(3, true, Some(6_000), 0.0)]);
+
+        assert_eq!(summary.runs.iter().map(|run| run.session).collect::<Vec<_>>(), [1, 2, 3]);
+        assert_eq!((summary.sessions, summary.succeeded), (3, 2));
+        assert!(!summary.all_succeeded());
+        assert_eq!(summary.time_to_goal_ms, Some(Spread { min: 6_000.0, mean: 7_000.0, max: 8_000.0 }));
+        assert_eq!(summary.overshoot, Some(Spread { min: 0.0, mean: 0.5, max: 1.0 }));
+        assert!(summary.to_string().contains("2 of 3 sessions succeeded (67%)"));
+
+        let json: serde_json::Value = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["runs"].as_array().unwrap().len(), 3);
+        assert_eq!(json["success_rate"], serde_json::json!(2.0 / 3.0));
+    }
+
+    #[test]
+    fn an_empty_batch_has_nothing_to_spread() {
+        let summary = Summary::new(Vec::new());
+
+        assert_eq!((summary.success_rate, summary.duration_ms.clone()), (0.0, None));
+        assert!(summary.all_succeeded()); //vacuously, the client refuses --sessions 0 anyway
+    }
+}
@@ -3,38 +3,35 @@
 use std::sync::Arc;
 use async_channel::{Receiver, Sender, unbounded};
 use tonic::metadata::MetadataValue;
-use tonic::{Request, Status};
-use tonic::transport::Channel;
+use tonic::{Code, Request, Status};
+use tonic_health::pb::health_check_response::ServingStatus;
+use tonic_health::pb::health_client::HealthClient;
+use tonic_health::pb::HealthCheckRequest;
+use tonic::transport::{Channel, Endpoint};
 use tokio::sync::{OnceCell};
 use tonic::codegen::InterceptedService;
 use tonic::service::Interceptor;
-use tokio::time::{Duration, interval};
-use log::{debug, info, warn};
-
-pub mod proto {
-    tonic::include_proto!("service");
-}
+use tokio::time::interval;
+use log::{debug, error, info, warn};
 
+mod batch;
 mod config;
+mod retry;
 
-use proto::controller_client::ControllerClient;
-use proto::ClientMsg;
-use crate::proto::server_msg::{Data, SimOver, SimStart, SimUpdate};
-use crate::proto::Area;
-
-#[derive(Clone)]
-struct ServiceState {
-    x: f32,
-}
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use clap::Parser;
+use batch::{SessionStats, Summary, Tracker};
+use config::Config;
+use seank_tonic_project_rs::{control, proto};
+use seank_tonic_project_rs::recording::Recorder;
+use retry::{Backoff, ErrorClass};
+use seank_tonic_project_rs::session::{Session, SessionError, Step};
 
-#[allow(dead_code)]
-#[derive(Clone, Debug)]
-struct ServiceInstruction {
-    b_minimum_x: f32,
-    b_maximum_x: f32,
-    g_minimum_x: f32,
-    g_maximum_x: f32,
-}
+use proto::controller_client::ControllerClient;
+use proto::{ClientMsg, RecordingHeader};
+use control::{Controller, ServiceInstruction, ServiceState};
 
 struct MyInterceptor {
     token: String
@@ -49,44 +46,75 @@ impl Interceptor for MyInterceptor {
     }
 }
 
-fn calculate_control_values(service_state: &ServiceState, prev_service_state: &ServiceState, instruction: &ServiceInstruction, dt: f32) -> i32 {
-    // there was some algorithm here. I deleted it to anonymise the code and hard coded the return value
-    let g_x = (instruction.g_minimum_x + instruction.g_maximum_x) / 2.0;
-    let distance_x = g_x - service_state.x;
-    distance_x as i32
-}
+// the name the server's health service knows the Controller service by
+const CONTROLLER_SERVICE: &str = "service.Controller";
+
+// asks grpc.health.v1.Health whether service.Controller is up before opening a session on it. a server without
+// a health service is given the benefit of the doubt, one that says it isn't serving is treated as unavailable
+async fn check_health(channel: Channel, token: &str) -> Result<(), SessionError> {
+    let mut health = HealthClient::with_interceptor(channel, MyInterceptor { token: token.to_string() });
+    let request = Request::new(HealthCheckRequest { service: CONTROLLER_SERVICE.to_string() });
 
+    match health.check(request).await {
+        Ok(response) => match response.into_inner().status() {
+            ServingStatus::Serving => {
+                debug!("{} is serving", CONTROLLER_SERVICE);
+                Ok(())
+            }
+            status => Err(SessionError::NotServing(status.as_str_name())),
+        },
+        Err(status) if status.code() == Code::Unimplemented => {
+            warn!("the server has no health service, skipping the health check");
+            Ok(())
+        }
+        Err(status) => Err(status.into()),
+    }
+}
 
 async fn establish_connection(
     state_receiver: Arc<Receiver<Option<ServiceState>>>,
     server_instruction: Arc<OnceCell<ServiceInstruction>>,
     navigation_success: Arc<OnceCell<bool>>,
-    config: config::Config,
-) -> Result<(ControllerClient<InterceptedService<Channel, MyInterceptor>>, tonic::Streaming<proto::ServerMsg>), Box<dyn std::error::Error>> {
-    let channel = Channel::from_shared(config.endpoint.to_string())?.connect().await?;
-    let mut client = ControllerClient::with_interceptor(channel, MyInterceptor { token: config.token.to_string() } );
-    let delta_time = 0.02;
+    mut controller: Box<dyn Controller>,
+    recorder: Option<Arc<Recorder>>,
+    sent: Arc<AtomicU64>,
+    config: &Config,
+) -> Result<(ControllerClient<InterceptedService<Channel, MyInterceptor>>, tonic::Streaming<proto::ServerMsg>), SessionError> {
+    let channel = Endpoint::from_shared(config.endpoint.clone())?.connect().await?;
+    if config.health_check {
+        check_health(channel.clone(), &config.token).await?;
+    }
+    let mut client = ControllerClient::with_interceptor(channel, MyInterceptor { token: config.token.clone() } );
+    let tick = config.tick();
+    let delta_time = tick.as_secs_f32();
 
     let outbound = async_stream::stream! {
-        let mut interval = interval(Duration::from_secs_f32(delta_time));
-        let mut prev_service_state = ServiceState { x: 0.0 };
+        let mut interval = interval(tick);
+        let mut prev_service_state: Option<ServiceState> = None;
 
-        while let (None, server_instruction, Ok(service_state_maybe)) =
-        (navigation_success.get(), server_instruction.get(), state_receiver.recv().await) {
+        while let (None, Ok(service_state_maybe)) = (navigation_success.get(), state_receiver.recv().await) {
             interval.tick().await;
 
-            match (service_state_maybe, server_instruction) {
+            // looked up after the state arrives, the SimStart that carries the instruction also carries the first state
+            let client_msg = match (service_state_maybe, server_instruction.get()) {
                 (Some(service_state), Some(instruction)) => {
-                    let c = calculate_control_values(&service_state, &prev_service_state, &instruction, delta_time);
-                    prev_service_state = service_state;
+                    // on the first tick there is nothing to compare with, so the point counts as standing still
+                    let prev = prev_service_state.as_ref().unwrap_or(&service_state);
+                    let c = controller.control(&service_state, prev, instruction, delta_time);
+                    prev_service_state = Some(service_state);
                     debug!("both information present: c:{:?}", c);
-                    yield ClientMsg { c };
+                    ClientMsg { c }
                 },
                 _ => {
                     debug!("Not supposed to happen but returning empty Service Client Message ");
-                    yield ClientMsg { c: 0 };
+                    ClientMsg { c: 0 }
                 },
+            };
+            if let Some(recorder) = &recorder {
+                recorder.sent(&client_msg);
             }
+            sent.fetch_add(1, Ordering::Relaxed);
+            yield client_msg;
         }
     };
 
@@ -96,66 +124,266 @@ async fn establish_connection(
     Ok((client, inbound))
 }
 
+#[derive(Parser, Debug)]
+#[command(name = "client", about = "Steers the simulation served by the Controller service")]
+struct Cli {
+    /// Path to a TOML configuration file, ./config.toml is read when there is one and this isn't given
+    #[arg(short, long, env = "CLIENT_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Server url, e.g. http://[::1]:50051
+    #[arg(long, env = "CLIENT_ENDPOINT")]
+    endpoint: Option<String>,
+
+    /// Value of the authorization header, e.g. "Bearer TOKEN"
+    #[arg(long, env = "CLIENT_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    /// Milliseconds between control values
+    #[arg(long, env = "CLIENT_TICK_MS")]
+    tick_ms: Option<u64>,
+
+    /// Seconds to wait between sessions
+    #[arg(long, env = "CLIENT_COOL_OFF_SECS")]
+    cool_off_secs: Option<u64>,
+
+    /// p, pid or bang-bang
+    #[arg(long, env = "CLIENT_CONTROLLER")]
+    controller: Option<String>,
+
+    /// Milliseconds to wait after the first failed session, doubled on every failure in a row
+    #[arg(long, env = "CLIENT_BACKOFF_INITIAL_MS")]
+    backoff_initial_ms: Option<u64>,
+
+    /// The longest wait between failed sessions, in milliseconds
+    #[arg(long, env = "CLIENT_BACKOFF_MAX_MS")]
+    backoff_max_ms: Option<u64>,
+
+    /// Record every session to a file in this directory, see recording.rs
+    #[arg(long = "record", env = "CLIENT_RECORD_DIR")]
+    record_dir: Option<PathBuf>,
+
+    /// Milliseconds without a message from the server before the session is given up on
+    #[arg(long, env = "CLIENT_STALL_TIMEOUT_MS")]
+    stall_timeout_ms: Option<u64>,
+
+    /// Ask the server's health service whether service.Controller is serving before every session
+    #[arg(long, env = "CLIENT_HEALTH_CHECK", num_args = 0..=1, default_missing_value = "true")]
+    health_check: Option<bool>,
+
+    /// Run this many sessions without retrying or cooling off, print a summary and exit, non-zero if any failed
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    sessions: Option<u64>,
+
+    /// How many of the --sessions to run at once
+    #[arg(long, default_value_t = 1, requires = "sessions", value_parser = clap::value_parser!(u64).range(1..))]
+    parallel: u64,
+
+    /// Also write the --sessions summary as JSON to this file, or - to print only the JSON
+    #[arg(long, requires = "sessions")]
+    report: Option<PathBuf>,
+}
+
+//defaults, then the config file, then environment variables and command line flags on top (clap
+//takes the flag when both are there)
+fn load_config(cli: Cli) -> Result<Config, String> {
+    let mut config = match &cli.config {
+        Some(path) => Config::from_file(path)?,
+        None if Path::new("config.toml").exists() => Config::from_file(Path::new("config.toml"))?,
+        None => Config::default(),
+    };
+
+    if let Some(endpoint) = cli.endpoint {
+        config.endpoint = endpoint;
+    }
+    if let Some(token) = cli.token {
+        config.token = token;
+    }
+    if let Some(tick_ms) = cli.tick_ms {
+        config.tick_ms = tick_ms;
+    }
+    if let Some(cool_off_secs) = cli.cool_off_secs {
+        config.cool_off_secs = cool_off_secs;
+    }
+    if let Some(controller) = cli.controller {
+        config.controller = controller;
+    }
+    if let Some(initial) = cli.backoff_initial_ms {
+        config.backoff_initial_ms = initial;
+    }
+    if let Some(max) = cli.backoff_max_ms {
+        config.backoff_max_ms = max;
+    }
+    if let Some(dir) = cli.record_dir {
+        config.record_dir = Some(dir);
+    }
+    if let Some(stall_timeout_ms) = cli.stall_timeout_ms {
+        config.stall_timeout_ms = stall_timeout_ms;
+    }
+    if let Some(health_check) = cli.health_check {
+        config.health_check = health_check;
+    }
+
+    config.validate()?;
+    Ok(config)
+}
+
+async fn forward(state_sender: &Sender<Option<ServiceState>>, state: Option<ServiceState>) {
+    state_sender.send(state).await.unwrap_or_else(|e| warn!("async-channel error, SendError is {:?}", e.to_string()));
+}
+
+// one Connection from start to SimOver, true when the simulation ended with success
+async fn run_session(config: &Config, controller: Box<dyn Controller>, tracker: &mut Tracker) -> Result<bool, SessionError> {
+    let (state_sender, state_receiver): (Sender<Option<ServiceState>>, Receiver<Option<ServiceState>>) = unbounded();
+    let state_receiver_arc = Arc::new(state_receiver);
+    let server_instruction = Arc::new(OnceCell::new());
+    let navigation_success = Arc::new(OnceCell::new());
+    let recorder = match &config.record_dir {
+        Some(dir) => {
+            let header = RecordingHeader { controller: config.controller.clone(), tick_ms: config.tick_ms };
+            let recorder = Recorder::create(dir, header).map_err(SessionError::Record)?;
+            info!("recording the session to {}", recorder.path().display());
+            Some(Arc::new(recorder))
+        }
+        None => None,
+    };
+    let (_, mut inbound) = establish_connection(state_receiver_arc, server_instruction.clone(), navigation_success.clone(), controller, recorder.clone(), tracker.sent(), config).await?;
+
+    let mut session = Session::default();
+    let result = loop {
+        let server_msg = match tokio::time::timeout(config.stall_timeout(), inbound.message()).await {
+            Ok(Ok(Some(server_msg))) => server_msg,
+            Ok(Ok(None)) => break Err(SessionError::ClosedEarly),
+            Ok(Err(status)) => break Err(status.into()),
+            Err(_) => break Err(SessionError::Stalled(config.stall_timeout())),
+        };
+        if let Some(recorder) = &recorder {
+            recorder.received(&server_msg);
+        }
+
+        let step = session.on_message(&server_msg);
+        if let Ok(step) = &step {
+            tracker.on_step(step);
+        }
+        match step {
+            Ok(Step::Started(instruction, state)) => {
+                let _ = server_instruction.set(instruction); //can't be set already, the session refuses a second start
+                forward(&state_sender, Some(state)).await;
+            }
+            Ok(Step::Moved(state)) => forward(&state_sender, Some(state)).await,
+            Ok(Step::Over { success, details }) => {
+                let outcome = if success { "success" } else { "failure" };
+                info!("\n\n******** Simulation ended with {} ******** {:?}\n", outcome, details);
+                let _ = navigation_success.set(success);
+                break Ok(success);
+            }
+            Ok(Step::Unknown) => {
+                forward(&state_sender, None).await;
+                warn!("unknown message {:?}", server_msg);
+            }
+            Err(e) => {
+                warn!("ending the session, {}: {:?}", e, server_msg);
+                break Err(e);
+            }
+        }
+    };
+
+    // closing the channel ends the outbound stream, so the server sees this side finish rather than vanish
+    state_sender.close();
+    result
+}
+
+// one of the --sessions workers, it takes the next session number until all `sessions` have been run
+async fn batch_worker(config: Arc<Config>, next: Arc<AtomicUsize>, sessions: usize) -> Vec<SessionStats> {
+    let mut runs = Vec::new();
+    loop {
+        let number = next.fetch_add(1, Ordering::Relaxed);
+        if number > sessions {
+            return runs;
+        }
+        let mut tracker = Tracker::default();
+        let result = match control::from_name(&config.controller) {
+            Ok(controller) => run_session(&config, controller, &mut tracker).await,
+            Err(problem) => Err(SessionError::Controller(problem)),
+        };
+        if let Err(e) = &result {
+            warn!("session {} failed: {}", number, e);
+        }
+        runs.push(tracker.finish(number, &result));
+    }
+}
+
+// the workers share this thread: rustc can't prove a session future Send (the outbound stream trips its
+// higher-ranked lifetime check), and sessions spend their time waiting on the network anyway
+async fn run_batch(config: Config, sessions: usize, parallel: usize) -> Vec<SessionStats> {
+    let (config, next) = (Arc::new(config), Arc::new(AtomicUsize::new(1)));
+    let local = tokio::task::LocalSet::new();
+    local.run_until(async move {
+        let mut workers = tokio::task::JoinSet::new();
+        for _ in 0..parallel.min(sessions) {
+            workers.spawn_local(batch_worker(config.clone(), next.clone(), sessions));
+        }
+
+        let mut runs = Vec::new();
+        while let Some(worker) = workers.join_next().await {
+            match worker {
+                Ok(worker_runs) => runs.extend(worker_runs),
+                Err(e) => error!("a batch worker panicked: {}", e),
+            }
+        }
+        runs
+    }).await
+}
+
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
     env_logger::init();
-    info!("Endpoint: {}", config::CONFIG.endpoint);
-    info!("Token: {}", config::CONFIG.token);
+    let cli = Cli::parse();
+    let (sessions, parallel, report) = (cli.sessions, cli.parallel, cli.report.clone());
+    let config = load_config(cli)?;
+    info!("Endpoint: {}", config.endpoint);
+    info!("Token: {}", config.redacted_token());
+    info!("Controller: {}, tick: {:?}, cool off: {:?}", config.controller, config.tick(), config.cool_off());
 
-    let empty_string = String::from("");
-    loop {
-        let (state_sender, state_receiver): (Sender<Option<ServiceState>>, Receiver<Option<ServiceState>>) = unbounded();
-        let state_receiver_arc = Arc::new(state_receiver);
-        let server_instruction = Arc::new(OnceCell::new());
-        let navigation_success = Arc::new(OnceCell::new());
-        let (_, mut inbound) = establish_connection(state_receiver_arc, server_instruction.clone(), navigation_success.clone(), config::CONFIG).await?;
-
-        while let Some(server_msg) = inbound.message().await? {
-            match &server_msg.data {
-                Some(Data::Start(SimStart {
-                                     l: Some(l),
-                                     limit: Some(Area { n_point: Some(boundary_min), m_point: Some(boundary_max) }),
-                                     g: Some(Area { n_point: Some(goal_min), m_point: Some(goal_max) })})) => {
-                    let state = ServiceState { x: l.x };
-
-                    let service_instruction = ServiceInstruction {
-                        b_minimum_x: boundary_min.x,
-                        b_maximum_x: boundary_max.x,
-                        g_minimum_x: goal_min.x,
-                        g_maximum_x: goal_max.x,
-                    };
-
-                    server_instruction.set(service_instruction).unwrap();
-                    state_sender.send(Some(state)).await.unwrap_or_else(|e| warn!("async-channel error, SendError is {:?}", e.to_string()));
-                }
+    if let Some(sessions) = sessions {
+        let summary = Summary::new(run_batch(config, sessions as usize, parallel as usize).await);
+        match report.as_deref() {
+            Some(path) if path == Path::new("-") => println!("{}", serde_json::to_string_pretty(&summary)?), //nothing else, so it can be piped
+            Some(path) => {
+                print!("{}", summary);
+                std::fs::write(path, serde_json::to_string_pretty(&summary)?)?;
+            }
+            None => print!("{}", summary),
+        }
+        return Ok(if summary.all_succeeded() { ExitCode::SUCCESS } else { ExitCode::FAILURE });
+    }
 
-                Some(Data::Start(SimStart { l: _, limit: _, g: _, })) => {
-                    panic!("this shouldn't happen, SimStart should provide all the necessary instructions needed");
-                }
-                Some(Data::Update(SimUpdate { l: Some(location) })) => {
-                    let state = ServiceState { x: location.x, };
-                    state_sender.send(Some(state)).await?;
-                }
-                Some(Data::Ended(SimOver { success: true, details })) => {
-                    info!("\n\n******** Simulation ended with success ******** {:?}\n", details.as_ref().unwrap_or(&empty_string));
-                    navigation_success.set(true).unwrap();
-                    break;
+    // the supervisor: a session that ran to the end is followed by the cool off, one that failed by a
+    // growing wait, and one the server refused ends the client (see retry.rs)
+    let mut backoff = Backoff::new(config.backoff_initial(), config.backoff_max());
+    loop {
+        let controller = control::from_name(&config.controller)?; //a fresh one every session, the pid integral starts over
+        match run_session(&config, controller, &mut Tracker::default()).await {
+            Ok(_) => {
+                backoff.reset();
+                info!("\nWaiting for {:?} (cool off period) before restarting the connection...\n\n", config.cool_off());
+                tokio::time::sleep(config.cool_off()).await;
+            }
+            Err(e) => match retry::classify(&e) {
+                ErrorClass::Fatal if matches!(e, SessionError::Unauthenticated(_)) => {
+                    error!("authentication failed with token {}, check `token` in the config: {}", config.redacted_token(), e);
+                    return Err(e.into());
                 }
-                Some(Data::Ended(SimOver { success: false, details })) => {
-                    info!("\n\n******** Simulation ended with failure ******** {:?}\n", details.as_ref().unwrap_or(&empty_string));
-                    navigation_success.set(false).unwrap();
-                    break;
+                ErrorClass::Fatal => {
+                    error!("the server refused the session, not retrying: {}", e);
+                    return Err(e.into());
                 }
-                _ => {
-                    state_sender.send(None).await?;
-                    warn!("unknown");
+                class => {
+                    let delay = backoff.next_delay();
+                    warn!("session failed ({}): {}, retrying in {:?}", class, e, delay);
+                    tokio::time::sleep(delay).await;
                 }
-            }
+            },
         }
-
-        info!("\nWaiting for 30 seconds (cool off period) before restarting the connection...\n\n");
-        drop(inbound);
-        drop(state_sender);
-        tokio::time::sleep(Duration::from_secs(30)).await;
     }
 }
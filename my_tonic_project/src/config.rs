@@ -1,16 +1,175 @@
-#![cfg_attr(rustfmt, rustfmt_skip)]
-#![allow(dead_code)]
+use serde::Deserialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use std::borrow::Cow;
+use seank_tonic_project_rs::control;
 
-#[derive(Debug, Clone)]
-#[allow(non_camel_case_types)]
+// the client settings come from four places, each one overriding the previous:
+// built in defaults -> the TOML file (--config, or ./config.toml when there is one) -> CLIENT_* environment
+// variables -> command line flags (see client.rs). nothing is baked in at build time any more, so pointing
+// the client at another server or token is just a restart.
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
-    pub endpoint: Cow<'static, str>,
-    pub token: Cow<'static, str>,
+    pub endpoint: String,
+    pub token: String, //sent as the authorization header, never logged as it is (see redacted_token)
+    pub tick_ms: u64, //how often a control value goes out
+    pub cool_off_secs: u64, //the wait between one session ending and the next one starting
+    pub controller: String, //p, pid or bang-bang, see control.rs
+    pub backoff_initial_ms: u64, //the wait after a failed session, doubled on every failure in a row
+    pub backoff_max_ms: u64, //up to this
+    pub health_check: bool, //probe grpc.health.v1.Health before every session
+    pub record_dir: Option<PathBuf>, //where to keep a recording of every session, none when unset
+    pub stall_timeout_ms: u64, //give up on a session when the server sends nothing for this long
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            endpoint: "http://[::1]:50051".to_string(),
+            token: String::new(),
+            tick_ms: 20,
+            cool_off_secs: 30,
+            controller: "pid".to_string(),
+            backoff_initial_ms: 500,
+            backoff_max_ms: 30_000,
+            health_check: false,
+            record_dir: None,
+            stall_timeout_ms: 5_000,
+        }
+    }
+}
+
+// the token as it may appear in logs: the scheme, if there is one, and nothing of the secret
+fn redact(token: &str) -> String {
+    match token.split_once(' ') {
+        _ if token.is_empty() => "(none)".to_string(),
+        Some((scheme, _)) => format!("{} ****", scheme),
+        None => "****".to_string(),
+    }
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("endpoint", &self.endpoint)
+            .field("token", &self.redacted_token())
+            .field("tick_ms", &self.tick_ms)
+            .field("cool_off_secs", &self.cool_off_secs)
+            .field("controller", &self.controller)
+            .field("backoff_initial_ms", &self.backoff_initial_ms)
+            .field("backoff_max_ms", &self.backoff_max_ms)
+            .field("health_check", &self.health_check)
+            .field("record_dir", &self.record_dir)
+            .field("stall_timeout_ms", &self.stall_timeout_ms)
+            .finish()
+    }
 }
 
-pub const CONFIG: Config = Config {
-    endpoint: Cow::Borrowed("http://[::1]:50051"),
-    token: Cow::Borrowed("Bearer MYTOKEN"),
-};
+impl Config {
+    pub fn from_file(path: &Path) -> Result<Config, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("invalid {}: {}", path.display(), e))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let mut problems = Vec::new();
+
+        if !self.endpoint.starts_with("http://") && !self.endpoint.starts_with("https://") {
+            problems.push(format!("endpoint must be an http:// or https:// url, got {:?}", self.endpoint));
+        }
+        if self.tick_ms == 0 {
+            problems.push("tick_ms must be at least 1".to_string());
+        }
+        if self.backoff_initial_ms == 0 || self.backoff_max_ms < self.backoff_initial_ms {
+            problems.push("backoff_initial_ms must be at least 1 and no more than backoff_max_ms".to_string());
+        }
+        if self.stall_timeout_ms <= self.tick_ms {
+            problems.push("stall_timeout_ms must be longer than tick_ms".to_string());
+        }
+        if let Err(problem) = control::from_name(&self.controller) {
+            problems.push(problem);
+        }
+
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(format!("invalid configuration:\n  {}", problems.join("\n  "))),
+        }
+    }
+
+    pub fn redacted_token(&self) -> String {
+        redact(&self.token)
+    }
+
+    pub fn tick(&self) -> Duration {
+        Duration::from_millis(self.tick_ms)
+    }
+
+    pub fn cool_off(&self) -> Duration {
+        Duration::from_secs(self.cool_off_secs)
+    }
+
+    pub fn backoff_initial(&self) -> Duration {
+        Duration::from_millis(self.backoff_initial_ms)
+    }
+
+    pub fn backoff_max(&self) -> Duration {
+        Duration::from_millis(self.backoff_max_ms)
+    }
+
+    pub fn stall_timeout(&self) -> Duration {
+        Duration::from_millis(self.stall_timeout_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_fields_keep_their_defaults() {
+        let config: Config = toml::from_str("endpoint = \"http://example.com:3001\"\ncontroller = \"p\"").unwrap();
+
+        assert_eq!(config.endpoint, "http://example.com:3001");
+        assert_eq!(config.controller, "p");
+        assert_eq!(config.tick(), Duration::from_millis(20));
+        assert_eq!(config.cool_off(), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn unknown_fields_are_refused() {
+        assert!(toml::from_str::<Config>("endpiont = \"http://example.com\"").is_err());
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let config = Config {
+            endpoint: "example.com".to_string(),
+            tick_ms: 0,
+            controller: "fuzzy".to_string(),
+            backoff_max_ms: 10,
+            stall_timeout_ms: 0,
+            ..Config::default()
+        };
+
+        let problems = config.validate().unwrap_err();
+
+        assert!(problems.contains("endpoint"));
+        assert!(problems.contains("tick_ms"));
+        assert!(problems.contains("unknown controller"));
+        assert!(problems.contains("backoff_initial_ms"));
+        assert!(problems.contains("stall_timeout_ms"));
+    }
+
+    #[test]
+    fn the_token_never_shows_up_in_debug_output() {
+        let config = Config { token: "Bearer MYTOKEN".to_string(), ..Config::default() };
+
+        assert_eq!(config.redacted_token(), "Bearer ****");
+        assert!(!format!("{:?}", config).contains("MYTOKEN"));
+        assert_eq!(redact("MYTOKEN"), "****");
+        assert_eq!(redact(""), "(none)");
+    }
+}
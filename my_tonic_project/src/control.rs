@@ -0,0 +1,245 @@
+// the client's side of the loop: given where the point is now, where it was a tick ago and what the server
+// asked for, work out the control value c to send back.
+//
+//   p          c = kp * error
+//   pid        c = kp * error + ki * ∫error dt - kd * dx/dt
+//   bang-bang  full push towards the goal, full brake once close enough, nothing when resting there
+//
+// error is measured from the middle of the goal area, and every controller keeps c within ±max_output.
+// which one the client uses is the `controller` setting in config.toml.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServiceState {
+    pub x: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServiceInstruction {
+    pub b_minimum_x: f32,
+    pub b_maximum_x: f32,
+    pub g_minimum_x: f32,
+    pub g_maximum_x: f32,
+}
+
+impl ServiceInstruction {
+    //the middle of the goal area, never outside the limit area whatever the server sent
+    pub fn setpoint(&self) -> f32 {
+        let centre = (self.g_minimum_x + self.g_maximum_x) / 2.0;
+        centre.clamp(self.b_minimum_x.min(self.b_maximum_x), self.b_maximum_x.max(self.b_minimum_x))
+    }
+}
+
+pub trait Controller: Send {
+    fn control(&mut self, state: &ServiceState, prev_state: &ServiceState, instruction: &ServiceInstruction, dt: f32) -> i32;
+}
+
+pub const CONTROLLERS: [&str; 3] = ["p", "pid", "bang-bang"];
+
+// a controller by its config name, with gains that suit the mock server's default dynamics
+pub fn from_name(name: &str) -> Result<Box<dyn Controller>, String> {
+    match name {
+        "p" => Ok(Box::new(Proportional::new(1.0, 100.0))),
+        "pid" => Ok(Box::new(Pid::new(4.0, 0.2, 3.0, 100.0))),
+        "bang-bang" => Ok(Box::new(BangBang::new(5.0, 0.2))),
+        _ => Err(format!("unknown controller {:?}, expected one of {}", name, CONTROLLERS.join(", "))),
+    }
+}
+
+fn to_control(output: f32, max_output: f32) -> i32 {
+    output.clamp(-max_output, max_output).round() as i32
+}
+
+//how fast x moved over the last tick
+fn velocity(state: &ServiceState, prev_state: &ServiceState, dt: f32) -> f32 {
+    if dt > 0.0 { (state.x - prev_state.x) / dt } else { 0.0 }
+}
+
+#[derive(Debug)]
+pub struct Proportional {
+    kp: f32,
+    max_output: f32,
+}
+
+impl Proportional {
+    pub fn new(kp: f32, max_output: f32) -> Proportional {
+        Proportional { kp, max_output }
+    }
+}
+
+impl Controller for Proportional {
+    fn control(&mut self, state: &ServiceState, _prev_state: &ServiceState, instruction: &ServiceInstruction, _dt: f32) -> i32 {
+        to_control(self.kp * (instruction.setpoint() - state.x), self.max_output)
+    }
+}
+
+#[derive(Debug)]
+pub struct Pid {
+    kp: f32,
+    ki: f32,
+    kd: f32,
+    max_output: f32,
+    integral: f32,
+}
+
+impl Pid {
+    pub fn new(kp: f32, ki: f32, kd: f32, max_output: f32) -> Pid {
+        Pid { kp, ki, kd, max_output, integral: 0.0 }
+    }
+}
+
+impl Controller for Pid {
+    fn control(&mut self, state: &ServiceState, prev_state: &ServiceState, instruction: &ServiceInstruction, dt: f32) -> i32 {
+        let error = instruction.setpoint() - state.x;
+        // the derivative is taken on x rather than on the error, so a new goal doesn't kick the output
+        let derivative = -velocity(state, prev_state, dt);
+
+        let integral = self.integral + error * dt;
+        let output = self.kp * error + self.ki * integral + self.kd * derivative;
+        let clamped = output.clamp(-self.max_output, self.max_output);
+
+        // anti-windup: while the output is saturated only let the integral shrink, otherwise it keeps growing
+        // during a long approach and overshoots the goal once it finally gets there
+        if output == clamped || integral.abs() < self.integral.abs() {
+            self.integral = integral;
+        }
+        to_control(clamped, self.max_output)
+    }
+}
+
+#[derive(Debug)]
+pub struct BangBang {
+    output: f32,
+    rest_speed: f32,
+}
+
+impl BangBang {
+    pub fn new(output: f32, rest_speed: f32) -> BangBang {
+        BangBang { output, rest_speed }
+    }
+}
+
+impl Controller for BangBang {
+    fn control(&mut self, state: &ServiceState, prev_state: &ServiceState, instruction: &ServiceInstruction, dt: f32) -> i32 {
+        let error = instruction.setpoint() - state.x;
+        let deadband = (instruction.g_maximum_x - instruction.g_minimum_x).abs() / 4.0;
+        let v = velocity(state, prev_state, dt);
+
+        let output = if error.abs() > deadband {
+            self.output.copysign(error)
+        } else if v.abs() > self.rest_speed {
+            -self.output.copysign(v)
+        } else {
+            0.0
+        };
+        to_control(output, self.output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.02;
+
+    fn instruction() -> ServiceInstruction {
+        ServiceInstruction { b_minimum_x: -50.0, b_maximum_x: 50.0, g_minimum_x: -26.0, g_maximum_x: -24.0 }
+    }
+
+    fn at(x: f32) -> ServiceState {
+        ServiceState { x }
+    }
+
+    // runs the controller along a made up trajectory, returning what it sent at every point
+    fn along(controller: &mut dyn Controller, xs: &[f32]) -> Vec<i32> {
+        xs.windows(2).map(|pair| controller.control(&at(pair[1]), &at(pair[0]), &instruction(), DT)).collect()
+    }
+
+    // the mock server's default world (mass 1, drag 1), closed around `controller` until x rests in the goal
+    fn settles(controller: &mut dyn Controller) -> bool {
+        let (mut x, mut v, mut prev, mut resting) = (0.0f32, 0.0f32, 0.0f32, 0);
+        for _ in 0..3000 {
+            let c = controller.control(&at(x), &at(prev), &instruction(), DT);
+            let a = (c as f32).clamp(-100.0, 100.0) - v;
+            prev = x;
+            v += a * DT;
+            x += v * DT;
+            assert!((-50.0..=50.0).contains(&x), "left the limit area at {}", x);
+
+            resting = if (-26.0..=-24.0).contains(&x) && v.abs() <= 0.5 { resting + 1 } else { 0 };
+            if resting >= 25 {
+                return true;
+            }
+        }
+        false
+    }
+
+    #[test]
+    fn controllers_are_picked_by_name() {
+        for name in CONTROLLERS {
+            assert!(from_name(name).is_ok(), "{}", name);
+        }
+        assert!(matches!(from_name("fuzzy"), Err(error) if error.contains("p, pid, bang-bang")));
+    }
+
+    #[test]
+    fn the_setpoint_stays_inside_the_limit_area() {
+        let instruction = ServiceInstruction { g_minimum_x: 60.0, g_maximum_x: 70.0, ..instruction() };
+
+        assert_eq!(instruction.setpoint(), 50.0);
+    }
+
+    #[test]
+    fn proportional_output_grows_with_the_distance_and_is_clamped() {
+        let mut p = Proportional::new(1.0, 10.0);
+
+        assert_eq!(along(&mut p, &[0.0, 0.0, -20.0, -25.0, -30.0]), [-10, -5, 0, 5]);
+    }
+
+    #[test]
+    fn pid_integrates_a_constant_error() {
+        let mut pid = Pid::new(0.0, 1.0, 0.0, 100.0);
+
+        let outputs = along(&mut pid, &[-20.0; 101]); //5 below the setpoint for 2 seconds
+
+        assert_eq!(outputs[0], 0);
+        assert_eq!(*outputs.last().unwrap(), -10);
+        assert!(outputs.windows(2).all(|pair| pair[0] >= pair[1]));
+    }
+
+    #[test]
+    fn pid_damps_on_the_measured_velocity() {
+        let mut pid = Pid::new(0.0, 0.0, 1.0, 100.0);
+
+        assert_eq!(along(&mut pid, &[0.0, -0.1, -0.2, -0.2]), [5, 5, 0]);
+    }
+
+    #[test]
+    fn pid_does_not_wind_up_while_saturated() {
+        let mut pid = Pid::new(1.0, 1.0, 0.0, 30.0);
+
+        //a long way from the goal for 10 seconds, so the output is stuck at the clamp
+        let outputs = along(&mut pid, &[0.0; 501]);
+        assert_eq!(*outputs.last().unwrap(), -30);
+
+        //having arrived, the output follows the error rather than pushing on for as long as it was saturated
+        let output = pid.control(&at(-25.0), &at(-25.0), &instruction(), DT);
+        assert!(output.abs() <= 5, "wound up to {}", output);
+    }
+
+    #[test]
+    fn bang_bang_pushes_brakes_and_rests() {
+        let mut bang = BangBang::new(5.0, 0.2);
+
+        assert_eq!(along(&mut bang, &[0.0, -1.0]), [-5]); //far above, push down
+        assert_eq!(along(&mut bang, &[-26.0, -27.0]), [5]); //far below, push up
+        assert_eq!(along(&mut bang, &[-24.9, -25.0]), [5]); //inside the deadband but still moving down, brake
+        assert_eq!(along(&mut bang, &[-25.0, -25.0]), [0]); //resting in the goal
+    }
+
+    #[test]
+    fn every_controller_settles_in_the_mock_servers_default_world() {
+        for name in CONTROLLERS {
+            assert!(settles(from_name(name).unwrap().as_mut()), "{} did not settle", name);
+        }
+    }
+}
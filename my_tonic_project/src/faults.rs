@@ -0,0 +1,316 @@
+use rand::rngs::StdRng;
+use rand::Rng;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use tonic::{Code, Status};
+
+use seank_tonic_project_rs::proto::server_msg::{Data, SimStart};
+use seank_tonic_project_rs::proto::ServerMsg;
+
+// ways for the mock server to misbehave on purpose, so the client can be tried against bad networks and buggy
+// servers. faults are written as comma separated key=value pairs, for every connection with --faults or for one
+// connection with the `faults` metadata key (which wins):
+//
+//   latency_ms=80          hold every update back this long
+//   jitter_ms=40           and a random 0..=40 ms more
+//   drop=0.1               lose 10% of the updates (the simulation carries on without telling the client)
+//   duplicate=0.05         send 5% of the updates twice
+//   abort_after=200        end the stream with an error status after 200 updates
+//   abort_code=internal    the status to end it with, unavailable by default
+//   start=no-position      a broken SimStart: no-position, no-limit, no-goal, or twice (a second SimStart)
+//   stall_after=100        after 100 updates, go quiet...
+//   stall_ms=10000         ...for this long, once
+//
+// e.g. grpcurl -H 'faults: drop=0.2,latency_ms=50' ... or mock_server --faults abort_after=100,abort_code=data_loss
+// a --replay is played back as it was recorded, faults or not.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BadStart {
+    NoPosition,
+    NoLimit,
+    NoGoal,
+    Twice,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Faults {
+    pub latency: Duration,
+    pub jitter: Duration,
+    pub drop: f64,
+    pub duplicate: f64,
+    pub abort_after: Option<u64>,
+    pub abort_code: Code,
+    pub start: Option<BadStart>,
+    pub stall_after: Option<u64>,
+    pub stall: Duration,
+}
+
+impl Default for Faults {
+    fn default() -> Self {
+        Faults {
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            drop: 0.0,
+            duplicate: 0.0,
+            abort_after: None,
+            abort_code: Code::Unavailable,
+            start: None,
+            stall_after: None,
+            stall: Duration::ZERO,
+        }
+    }
+}
+
+const CODES: [(&str, Code); 16] = [
+    ("cancelled", Code::Cancelled),
+    ("unknown", Code::Unknown),
+    ("invalid_argument", Code::InvalidArgument),
+    ("deadline_exceeded", Code::DeadlineExceeded),
+    ("not_found", Code::NotFound),
+    ("already_exists", Code::AlreadyExists),
+    ("permission_denied", Code::PermissionDenied),
+    ("resource_exhausted", Code::ResourceExhausted),
+    ("failed_precondition", Code::FailedPrecondition),
+    ("aborted", Code::Aborted),
+    ("out_of_range", Code::OutOfRange),
+    ("unimplemented", Code::Unimplemented),
+    ("internal", Code::Internal),
+    ("unavailable", Code::Unavailable),
+    ("data_loss", Code::DataLoss),
+    ("unauthenticated", Code::Unauthenticated),
+];
+
+fn number<T: FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("{} should be a number, got {:?}", key, value))
+}
+
+fn rate(key: &str, value: &str) -> Result<f64, String> {
+    match number(key, value)? {
+        rate if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("{} is a rate between 0 and 1, got {}", key, value)),
+    }
+}
+
+impl FromStr for Faults {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Faults, String> {
+        let mut faults = Faults::default();
+
+        for pair in spec.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').ok_or_else(|| format!("expected key=value, got {:?}", pair))?;
+            let (key, value) = (key.trim(), value.trim());
+            match key {
+                "latency_ms" => faults.latency = Duration::from_millis(number(key, value)?),
+                "jitter_ms" => faults.jitter = Duration::from_millis(number(key, value)?),
+                "drop" => faults.drop = rate(key, value)?,
+                "duplicate" => faults.duplicate = rate(key, value)?,
+                "abort_after" => faults.abort_after = Some(number(key, value)?),
+                "abort_code" => {
+                    faults.abort_code = CODES.iter()
+                        .find(|(name, _)| *name == value)
+                        .map(|(_, code)| *code)
+                        .ok_or_else(|| format!("unknown status code {:?}, e.g. unavailable, internal or data_loss", value))?;
+                }
+                "start" => {
+                    faults.start = Some(match value {
+                        "no-position" => BadStart::NoPosition,
+                        "no-limit" => BadStart::NoLimit,
+                        "no-goal" => BadStart::NoGoal,
+                        "twice" => BadStart::Twice,
+                        _ => return Err(format!("start is no-position, no-limit, no-goal or twice, got {:?}", value)),
+                    });
+                }
+                "stall_after" => faults.stall_after = Some(number(key, value)?),
+                "stall_ms" => faults.stall = Duration::from_millis(number(key, value)?),
+                _ => return Err(format!("unknown fault {:?}", key)),
+            }
+        }
+        Ok(faults)
+    }
+}
+
+// only the faults that are switched on, e.g. "latency 80ms + up to 40ms, drop 0.1"
+impl fmt::Display for Faults {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if !self.latency.is_zero() || !self.jitter.is_zero() {
+            parts.push(format!("latency {:?} + up to {:?}", self.latency, self.jitter));
+        }
+        if self.drop > 0.0 {
+            parts.push(format!("drop {}", self.drop));
+        }
+        if self.duplicate > 0.0 {
+            parts.push(format!("duplicate {}", self.duplicate));
+        }
+        if let Some(after) = self.abort_after {
+            parts.push(format!("abort with {:?} after {} updates", self.abort_code, after));
+        }
+        if let Some(start) = self.start {
+            parts.push(format!("start {:?}", start));
+        }
+        if let Some(after) = self.stall_after {
+            parts.push(format!("stall {:?} after {} updates", self.stall, after));
+        }
+
+        match parts.is_empty() {
+            true => write!(f, "none"),
+            false => write!(f, "{}", parts.join(", ")),
+        }
+    }
+}
+
+impl Faults {
+    //the SimStart messages to send in place of `start`
+    pub fn start_msgs(&self, start: ServerMsg) -> Vec<ServerMsg> {
+        let Some(Data::Start(sim_start)) = &start.data else { return vec![start] };
+        let broken = |sim_start: SimStart| vec![ServerMsg { data: Some(Data::Start(sim_start)) }];
+
+        match self.start {
+            None => vec![start],
+            Some(BadStart::NoPosition) => broken(SimStart { l: None, ..sim_start.clone() }),
+            Some(BadStart::NoLimit) => broken(SimStart { limit: None, ..sim_start.clone() }),
+            Some(BadStart::NoGoal) => broken(SimStart { g: None, ..sim_start.clone() }),
+            Some(BadStart::Twice) => vec![start.clone(), start],
+        }
+    }
+
+    //how long to hold the next update back
+    pub fn delay(&self, rng: &mut StdRng) -> Duration {
+        let jitter = match self.jitter.is_zero() {
+            true => Duration::ZERO,
+            false => rng.gen_range(Duration::ZERO..=self.jitter),
+        };
+        self.latency + jitter
+    }
+
+    //how many times the next update goes out: 0 when it is dropped, 2 when it is duplicated
+    pub fn copies(&self, rng: &mut StdRng) -> usize {
+        if rng.gen_bool(self.drop) {
+            0
+        } else if rng.gen_bool(self.duplicate) {
+            2
+        } else {
+            1
+        }
+    }
+
+    //the status to end the stream with, once `sent` updates have gone out
+    pub fn abort(&self, sent: u64) -> Option<Status> {
+        match self.abort_after {
+            Some(after) if sent >= after => Some(Status::new(self.abort_code, format!("fault injection: aborted after {} updates", sent))),
+            _ => None,
+        }
+    }
+
+    //how long to go quiet for, once `sent` updates have gone out
+    pub fn stall(&self, sent: u64) -> Option<Duration> {
+        match self.stall_after {
+            Some(after) if sent == after && !self.stall.is_zero() => Some(self.stall),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use seank_tonic_project_rs::proto::{Area, Point};
+    use rand::SeedableRng;
+
+    fn start() -> ServerMsg {
+        let point = |x| Some(Point { x });
+        ServerMsg {
+            data: Some(Data::Start(SimStart {
+                l: point(0.0),
+                limit: Some(Area { n_point: point(-50.0), m_point: point(50.0) }),
+                g: Some(Area { n_point: point(-26.0), m_point: point(-24.0) }),
+            })),
+        }
+    }
+
+    #[test]
+    fn an_empty_spec_is_no_faults() {
+        assert_eq!("".parse::<Faults>().unwrap(), Faults::default());
+        assert_eq!(Faults::default().to_string(), "none");
+        assert_eq!("drop=0.1,latency_ms=80".parse::<Faults>().unwrap().to_string(), "latency 80ms + up to 0ns, drop 0.1");
+    }
+
+    #[test]
+    fn every_key_is_parsed() {
+        let faults: Faults = "latency_ms=80, jitter_ms=40,drop=0.1,duplicate=0.05,abort_after=200,abort_code=data_loss,start=twice,stall_after=100,stall_ms=10000"
+            .parse().unwrap();
+
+        assert_eq!(faults, Faults {
+            latency: Duration::from_millis(80),
+            jitter: Duration::from_millis(40),
+            drop: 0.1,
+            duplicate: 0.05,
+            abort_after: Some(200),
+            abort_code: Code::DataLoss,
+            start: Some(BadStart::Twice),
+            stall_after: Some(100),
+            stall: Duration::from_secs(10),
+        });
+    }
+
+    #[test]
+    fn bad_specs_say_what_is_wrong() {
+        assert!("drop=2".parse::<Faults>().unwrap_err().contains("between 0 and 1"));
+        assert!("latency_ms=soon".parse::<Faults>().unwrap_err().contains("latency_ms should be a number"));
+        assert!("abort_code=oops".parse::<Faults>().unwrap_err().contains("unknown status code"));
+        assert!("wobble=1".parse::<Faults>().unwrap_err().contains("unknown fault"));
+        assert!("drop".parse::<Faults>().unwrap_err().contains("key=value"));
+    }
+
+    #[test]
+    fn broken_starts_leave_out_what_they_say() {
+        let sent = |spec: &str| spec.parse::<Faults>().unwrap().start_msgs(start());
+        let sim_start = |messages: &[ServerMsg]| match &messages[0].data {
+            Some(Data::Start(sim_start)) => sim_start.clone(),
+            other => panic!("expected a start, got {:?}", other),
+        };
+
+        assert!(sim_start(&sent("start=no-position")).l.is_none());
+        assert!(sim_start(&sent("start=no-limit")).limit.is_none());
+        assert!(sim_start(&sent("start=no-goal")).g.is_none());
+        assert_eq!(sent("start=twice"), [start(), start()]);
+        assert_eq!(sent(""), [start()]);
+    }
+
+    #[test]
+    fn drop_and_duplicate_rates_are_followed() {
+        let mut rng = StdRng::seed_from_u64(1);
+
+        assert_eq!("drop=1".parse::<Faults>().unwrap().copies(&mut rng), 0);
+        assert_eq!("duplicate=1".parse::<Faults>().unwrap().copies(&mut rng), 2);
+        assert_eq!(Faults::default().copies(&mut rng), 1);
+
+        let half: Faults = "drop=0.5".parse().unwrap();
+        let dropped = (0..1000).filter(|_| half.copies(&mut rng) == 0).count();
+        assert!((400..600).contains(&dropped), "dropped {} of 1000", dropped);
+    }
+
+    #[test]
+    fn delays_stay_within_latency_and_jitter() {
+        let faults: Faults = "latency_ms=80,jitter_ms=40".parse().unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+
+        for _ in 0..100 {
+            let delay = faults.delay(&mut rng);
+            assert!(delay >= Duration::from_millis(80) && delay <= Duration::from_millis(120), "{:?}", delay);
+        }
+    }
+
+    #[test]
+    fn abort_and_stall_happen_when_they_are_due() {
+        let faults: Faults = "abort_after=3,abort_code=internal,stall_after=2,stall_ms=500".parse().unwrap();
+
+        assert!(faults.abort(2).is_none());
+        assert_eq!(faults.abort(3).unwrap().code(), Code::Internal);
+        assert_eq!(faults.stall(1), None);
+        assert_eq!(faults.stall(2), Some(Duration::from_millis(500)));
+        assert_eq!(faults.stall(3), None);
+    }
+}
@@ -0,0 +1,37 @@
+// the modules more than one binary needs: the generated messages, the controllers, the client's handling of
+// the server's messages (which replay_diff runs offline) and recordings, written by the client and read by
+// the mock server and replay_diff. the rest lives next to the one binary that uses it
+
+pub mod proto {
+    tonic::include_proto!("service");
+
+    // written by build.rs, lets the reflection service describe service.Controller to grpcurl and friends
+    pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("service_descriptor");
+}
+
+pub mod control;
+pub mod recording;
+pub mod session;
+
+use std::error::Error;
+
+// the io::Error at the bottom of an error, if a broken connection is what caused it
+pub fn match_for_io_error<'a>(err: &'a (dyn Error + 'static)) -> Option<&'a std::io::Error> {
+    let mut err = err;
+
+    loop {
+        if let Some(io_err) = err.downcast_ref::<std::io::Error>() {
+            return Some(io_err);
+        }
+
+        // h2::Error do not expose std::io::Error with `source()`
+        // https://github.com/hyperium/h2/pull/462
+        if let Some(h2_err) = err.downcast_ref::<h2::Error>() {
+            if let Some(io_err) = h2_err.get_io() {
+                return Some(io_err);
+            }
+        }
+
+        err = err.source()?;
+    }
+}
@@ -1,101 +1,266 @@
 use std::{error::Error, pin::Pin};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::time;
-use tokio_stream::{Stream, StreamExt};
+use clap::Parser;
+use log::{info, warn};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use tokio::sync::{mpsc, watch};
+use tokio::time::{self, MissedTickBehavior};
+use tokio_stream::Stream;
 
 use tonic::{Request, Response, Status, Streaming};
 use tonic::transport::Server;
 
+use seank_tonic_project_rs::{match_for_io_error, proto, recording};
 use proto::{ClientMsg, ServerMsg};
-use proto::controller_server::Controller;
-use crate::proto::server_msg::{Data, SimOver, SimStart, SimUpdate};
-use crate::proto::{Area, Point};
+use proto::admin_server::AdminServer;
+use proto::controller_server::{Controller, ControllerServer};
+use proto::server_msg::{Data, SimOver, SimStart, SimUpdate};
+use proto::{Area, Point};
+use proto::record::Event;
+use crate::auth::AuthInterceptor;
+use crate::faults::Faults;
+use crate::registry::{AdminService, Registry, Slot};
+use crate::scenario::{Range, Scenario, Scenarios};
+use crate::simulation::{Outcome, Simulation};
 
-
-pub mod proto {
-    tonic::include_proto!("service");
-}
+mod auth;
+mod faults;
+mod registry;
+mod scenario;
+mod simulation;
 
 type ResponseStream = Pin<Box<dyn Stream<Item=Result<ServerMsg, Status>> + Send + 'static>>;
 
-fn match_for_io_error(err_status: &Status) -> Option<&std::io::Error> {
-    let mut err: &(dyn Error + 'static) = err_status;
+fn area(range: &Range) -> Area {
+    Area { n_point: Some(Point { x: range.min }), m_point: Some(Point { x: range.max }) }
+}
+
+fn start_msg(scenario: &Scenario) -> ServerMsg {
+    ServerMsg {
+        data: Some(Data::Start(SimStart {
+            l: Some(Point { x: scenario.start }),
+            limit: Some(area(&scenario.limit)),
+            g: Some(area(&scenario.goal)),
+        }))
+    }
+}
 
+// reads the client's control values as they come in and keeps the latest one in `control`.
+// the simulation ticks at its own pace and uses whatever was sent last, so a slow client just steers late.
+async fn read_controls(mut inbound: Streaming<ClientMsg>, control: watch::Sender<i32>) {
     loop {
-        if let Some(io_err) = err.downcast_ref::<std::io::Error>() {
-            return Some(io_err);
+        match inbound.message().await {
+            Ok(Some(message)) => {
+                if control.send(message.c).is_err() {
+                    break; // the simulation is over
+                }
+            }
+            Ok(None) => break,
+            Err(status) => {
+                match match_for_io_error(&status) {
+                    Some(io_err) if io_err.kind() == ErrorKind::BrokenPipe => info!("client disconnected: broken pipe"),
+                    _ => warn!("error reading client controls: {}", status),
+                }
+                break;
+            }
         }
+    }
+}
+
+
+#[derive(Parser, Debug)]
+#[command(name = "mock_server", about = "Local stand-in for the simulation server")]
+struct Cli {
+    /// Scenario files (TOML, or JSON when they end in .json), the built-in default scenario when there are none
+    scenarios: Vec<PathBuf>,
+
+    /// Address to listen on
+    #[arg(short, long, default_value = "[::1]:50051")]
+    address: String,
+
+    /// Run this scenario for every connection that doesn't ask for one, instead of taking turns
+    #[arg(short, long)]
+    scenario: Option<String>,
+
+    /// Bearer token clients must send, can be given more than once. without any, every client is let in
+    #[arg(short, long = "token", env = "MOCK_SERVER_TOKENS", value_delimiter = ',', hide_env_values = true)]
+    tokens: Vec<String>,
+
+    /// Play this recording (see the client's --record) to every connection instead of simulating
+    #[arg(long, conflicts_with_all = ["scenarios", "scenario"])]
+    replay: Option<PathBuf>,
+
+    /// Misbehave on every connection that doesn't ask otherwise, e.g. drop=0.1,latency_ms=50 (see faults.rs)
+    #[arg(long, default_value = "")]
+    faults: Faults,
 
-        // h2::Error do not expose std::io::Error with `source()`
-        // https://github.com/hyperium/h2/pull/462
-        if let Some(h2_err) = err.downcast_ref::<h2::Error>() {
-            if let Some(io_err) = h2_err.get_io() {
-                return Some(io_err);
+    /// How many sessions may run at once, more connections are turned away with resource_exhausted
+    #[arg(long, default_value_t = 64, value_parser = clap::value_parser!(u64).range(1..))]
+    max_sessions: u64,
+}
+
+// a connection can ask for a scenario by name with this metadata key, e.g. grpcurl -H 'scenario: noisy'
+const SCENARIO_KEY: &str = "scenario";
+// and for its own faults, e.g. grpcurl -H 'faults: start=no-goal'. an empty value asks for none
+const FAULTS_KEY: &str = "faults";
+
+#[derive(Debug)]
+pub struct MockRPCServer {
+    scenarios: Scenarios,
+    fixed: Option<String>,
+    replay: Option<Arc<Vec<(Duration, ServerMsg)>>>, //each message with when it is due, see replay()
+    faults: Faults,
+    registry: Arc<Registry>, //every running session, shared with AdminService
+}
+
+// the server side of a recording, sent with the timing it was received with. the client still steers, but
+// nothing it sends changes what comes back, which is the point: the same session every time
+fn replay(messages: Arc<Vec<(Duration, ServerMsg)>>, mut control: watch::Receiver<i32>, slot: Slot) -> ResponseStream {
+    let output = async_stream::try_stream! {
+        let started = time::Instant::now();
+        for (ticks, (due, message)) in (1..).zip(messages.iter()) {
+            time::sleep_until(started + *due).await;
+            if control.has_changed().is_err() {
+                info!("session {}: client went away during the replay", slot.id());
+                break;
+            }
+            control.mark_unchanged();
+            match &message.data {
+                Some(Data::Update(SimUpdate { l: Some(point) })) => slot.update(point.x, ticks),
+                Some(Data::Ended(over)) => slot.finish(over.details.as_deref().unwrap_or("over")),
+                _ => {}
             }
+            yield message.clone();
         }
+    };
+    Box::pin(output)
+}
 
-        err = match err.source() {
-            Some(err) => err,
-            None => return None,
-        };
+// a message for the client and when the faults let it arrive
+type Delivery = (time::Instant, Result<ServerMsg, Status>);
+
+// steps the simulation every tick and queues what the client is to see of it. the faults only decide when (and
+// whether) each message arrives, the simulation keeps its pace whatever the network is doing: a stall or some
+// latency holds back delivery, never the next step. like a real stream, later messages never overtake earlier ones
+async fn simulate(mut simulation: Simulation, faults: Faults, mut rng: StdRng, mut control: watch::Receiver<i32>,
+                  slot: Arc<Slot>, queue: mpsc::UnboundedSender<Delivery>) {
+    let mut interval = time::interval(simulation.scenario().tick);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay); //a late tick is late, not made up for with a burst
+
+    let mut quiet_until = time::Instant::now(); //nothing arrives before this
+    for message in faults.start_msgs(start_msg(simulation.scenario())) {
+        let _ = queue.send((quiet_until, Ok(message)));
     }
-}
+    let mut updates = 0;
+    let mut ticks = 0;
 
+    loop {
+        interval.tick().await;
 
-static SERVER_START_MSG: ServerMsg = ServerMsg {
-    data: Some(Data::Start(SimStart {
-        l: Some(Point { x: 0.0 }),
-        limit: Some(Area { n_point: Some(Point { x: -50.0 }), m_point: Some(Point { x: 50.0 }) }),
-        g: Some(Area { n_point: Some(Point { x: -26.0 }), m_point: Some(Point { x: -24.0 }) }),
-    }))
-};
+        // the client closed its side, nobody is steering any more
+        if control.has_changed().is_err() || queue.is_closed() {
+            info!("session {}: client went away at x = {}", slot.id(), simulation.position());
+            break;
+        }
+        let c = *control.borrow_and_update();
 
+        let outcome = simulation.step(c);
+        ticks += 1;
+        slot.update(simulation.position(), ticks);
 
-#[derive(Debug)]
-pub struct MockRPCServer {}
+        let now = time::Instant::now();
+        if let Some(stall) = faults.stall(updates) {
+            info!("session {}: stalling for {:?}", slot.id(), stall);
+            quiet_until = quiet_until.max(now) + stall;
+        }
+        let due = (now + faults.delay(&mut rng)).max(quiet_until);
+        quiet_until = due;
+
+        match outcome {
+            Outcome::Running => {
+                if let Some(status) = faults.abort(updates) {
+                    info!("session {}: aborting with {:?} at x = {}", slot.id(), status.code(), simulation.position());
+                    slot.finish(&format!("aborted with {:?}", status.code()));
+                    let _ = queue.send((due, Err(status)));
+                    break;
+                }
+                updates += 1;
+
+                let update = ServerMsg {
+                    data: Some(Data::Update(SimUpdate {
+                        l: Some(Point { x: simulation.reading() }),
+                    }))
+                };
+                for _ in 0..faults.copies(&mut rng) {
+                    let _ = queue.send((due, Ok(update.clone())));
+                }
+            }
+            outcome => {
+                info!("session {}: simulation over at x = {}, v = {}: {}", slot.id(), simulation.position(), simulation.velocity(), outcome.details());
+                slot.finish(outcome.details());
+                let over = ServerMsg {
+                    data: Some(Data::Ended(SimOver {
+                        success: outcome == Outcome::Reached,
+                        details: Some(outcome.details().to_owned()),
+                    }))
+                };
+                let _ = queue.send((due, Ok(over)));
+                break;
+            }
+        }
+    }
+}
+
+impl MockRPCServer {
+    fn scenario_for<T>(&self, request: &Request<T>) -> Result<Scenario, String> {
+        let asked = match request.metadata().get(SCENARIO_KEY) {
+            Some(value) => Some(value.to_str().map_err(|_| "the scenario name is not ascii".to_string())?),
+            None => self.fixed.as_deref(),
+        };
+        self.scenarios.pick(asked)
+    }
+
+    fn faults_for<T>(&self, request: &Request<T>) -> Result<Faults, String> {
+        match request.metadata().get(FAULTS_KEY) {
+            Some(value) => value.to_str().map_err(|_| "the faults are not ascii".to_string())?.parse(),
+            None => Ok(self.faults.clone()),
+        }
+    }
+}
 
 #[tonic::async_trait]
 impl Controller for MockRPCServer {
     type ConnectionStream = ResponseStream;
 
     async fn connection(&self, request: Request<Streaming<ClientMsg>>) -> Result<Response<Self::ConnectionStream>, Status> {
-        let mut stream = request.into_inner();
-        let mut count = 0;
-        let mut interval = time::interval(Duration::from_secs_f32(0.02));
-        let output = async_stream::try_stream! {
+        if let Some(messages) = &self.replay {
+            let slot = self.registry.open("replay", request.remote_addr()).map_err(Status::resource_exhausted)?;
+            info!("session {}: replaying {} messages", slot.id(), messages.len());
+            let (control_sender, control) = watch::channel(0);
+            tokio::spawn(read_controls(request.into_inner(), control_sender));
+            return Ok(Response::new(replay(messages.clone(), control, slot)));
+        }
 
-            // todo this doesn't work
-            // while let Some(client_msg) = stream.next().await {
-            //     match client_msg {
-            //         Ok(message) => {
-            //             println!("started 4");
-            //             yield SERVER_START_MSG.clone()
-            //         },
-            //         Err(e) => {
-            //             println!("started 5");
-            //             yield SERVER_START_MSG.clone()
-            //         }
-            //     }
-            // }
-
-            loop {
-                interval.tick().await;
-
-                if count == 0 {
-                    yield SERVER_START_MSG.clone()
-                } else if count > 300 {
-                    yield ServerMsg {
-                        data: Some(Data::Ended(SimOver { success: true, details: Some("success".to_owned()), }))
-                    }
-                } else {
-                    yield ServerMsg {
-                        data: Some(Data::Update(SimUpdate {
-                            l: Some(Point { x: -27.108433 }),
-                        }))
-                    }
-                }
+        let scenario = self.scenario_for(&request).map_err(Status::not_found)?;
+        let faults = self.faults_for(&request).map_err(Status::invalid_argument)?;
+        let slot = self.registry.open(&scenario.name, request.remote_addr()).map_err(Status::resource_exhausted)?;
+        info!("session {}: starting scenario {:?}, faults: {}", slot.id(), scenario.name, faults);
+
+        let (control_sender, control) = watch::channel(0);
+        tokio::spawn(read_controls(request.into_inner(), control_sender));
 
-                count += 1;
+        let slot = Arc::new(slot);
+        let (queue, mut deliveries) = mpsc::unbounded_channel();
+        tokio::spawn(simulate(Simulation::new(scenario), faults, StdRng::from_entropy(), control, slot.clone(), queue));
+        let output = async_stream::try_stream! {
+            let _slot = slot; //the session is running until its last message is out
+            while let Some((due, message)) = deliveries.recv().await {
+                time::sleep_until(due).await;
+                yield message?;
             }
         };
 
@@ -107,13 +272,105 @@ impl Controller for MockRPCServer {
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let server = MockRPCServer {};
+    env_logger::init();
+    let cli = Cli::parse();
+
+    let scenarios = match cli.scenarios.as_slice() {
+        [] => Scenarios::default(),
+        paths => Scenarios::new(scenario::load(paths)?),
+    };
+    if let Some(name) = &cli.scenario {
+        scenarios.pick(Some(name))?; //fail now rather than on every connection
+    }
+
+    // a recording keeps both directions, only what the server sent is played back
+    let replay = match &cli.replay {
+        Some(path) => {
+            let messages: Vec<_> = recording::read(path)?.into_iter()
+                .filter_map(|record| match record.event {
+                    Some(Event::Received(message)) => Some((Duration::from_micros(record.at_micros), message)),
+                    _ => None,
+                })
+                .collect();
+            info!("replaying {} messages from {}", messages.len(), path.display());
+            Some(Arc::new(messages))
+        }
+        None => {
+            info!("scenarios: {}", scenarios.names().join(", "));
+            None
+        }
+    };
+
+    if cli.faults != Faults::default() {
+        info!("faults: {}", cli.faults);
+    }
+    let registry = Registry::new(cli.max_sessions as usize);
+    let admin = AdminService { registry: registry.clone() };
+    let server = MockRPCServer { scenarios, fixed: cli.scenario, replay, faults: cli.faults, registry };
+    let auth = AuthInterceptor::new(cli.tokens);
+    if auth.is_open() {
+        info!("no --token given, every client is let in");
+    }
+
+    // grpc.health.v1.Health answers SERVING for service.Controller (and for "", the server as a whole)
+    let (mut health_reporter, health_service) = tonic_health::server::health_reporter();
+    health_reporter.set_serving::<ControllerServer<MockRPCServer>>().await;
+
+    // grpc.reflection.v1alpha.ServerReflection, so `grpcurl -plaintext [::1]:50051 list` works without the proto file
+    let reflection_service = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(proto::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        .build()?;
 
     Server::builder()
-        .add_service(proto::controller_server::ControllerServer::new(server))
-        .serve("[::1]:50051".parse().unwrap())
+        .add_service(health_service)
+        .add_service(reflection_service)
+        .add_service(AdminServer::with_interceptor(admin, auth.clone()))
+        .add_service(ControllerServer::with_interceptor(server, auth))
+        .serve(cli.address.parse()?)
         .await?;
 
     Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // runs the default scenario with `faults` for `ticks` ticks, then says how many steps the simulation took
+    // and how many messages the client would have had by then
+    async fn run(faults: &str, ticks: u32) -> (u64, usize) {
+        let registry = Registry::new(1);
+        let slot = Arc::new(registry.open("default", None).unwrap());
+        let scenario = Scenario::default();
+        let tick = scenario.tick;
+        let (_control_sender, control) = watch::channel(0);
+        let (queue, mut deliveries) = mpsc::unbounded_channel();
+        let faults = faults.parse().unwrap();
+        tokio::spawn(simulate(Simulation::new(scenario), faults, StdRng::seed_from_u64(1), control, slot, queue));
+
+        time::sleep(tick * ticks + tick / 2).await;
+        let steps = registry.list()[0].ticks;
+        let mut arrived = 0;
+        while let Ok((due, _)) = deliveries.try_recv() {
+            if due <= time::Instant::now() {
+                arrived += 1;
+            }
+        }
+        (steps, arrived)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn latency_and_stalls_hold_back_updates_not_the_simulation() {
+        let (steps, arrived) = run("", 50).await;
+        assert_eq!((steps, arrived), (51, 52)); //a step on every tick, the first straight away, and SimStart
+
+        let (steps, arrived) = run("latency_ms=200,jitter_ms=20", 50).await;
+        assert_eq!(steps, 51);
+        assert!((40..=42).contains(&arrived), "{} arrived", arrived);
+
+        let (steps, arrived) = run("stall_after=10,stall_ms=10000", 50).await;
+        assert_eq!(steps, 51);
+        assert_eq!(arrived, 11); //SimStart and the updates before the stall
+    }
+}
@@ -0,0 +1,149 @@
+use log::warn;
+use prost::Message;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::proto::record::Event;
+use crate::proto::{ClientMsg, Record, RecordingHeader, ServerMsg};
+
+// a recording is one session as the client saw it: a header saying which controller and tick it ran with, then
+// every ServerMsg that came in and every ClientMsg that went out, in order, each as a length-delimited Record
+// (proto/recording.proto) stamped with the time since the session started. the client writes them with --record DIR, one file per session, the mock server plays
+// them back with --replay FILE and replay_diff runs a controller against them offline.
+
+// numbers the recordings this process makes, batch sessions start theirs within the same millisecond
+static NEXT: AtomicU64 = AtomicU64::new(1);
+
+pub struct Recorder {
+    path: PathBuf,
+    started: Instant,
+    file: Mutex<BufWriter<File>>,
+}
+
+impl Recorder {
+    //a new recording in `dir`, named after the time it started, the process and how many it made before,
+    //starting with `header`. an existing file is never overwritten
+    pub fn create(dir: &Path, header: RecordingHeader) -> io::Result<Recorder> {
+        fs::create_dir_all(dir)?;
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+        let number = NEXT.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!("session-{}-{}-{}.rec", millis, std::process::id(), number));
+        let file = OpenOptions::new().write(true).create_new(true).open(&path)?;
+        let recorder = Recorder { path, started: Instant::now(), file: Mutex::new(BufWriter::new(file)) };
+        recorder.write(Event::Header(header));
+        Ok(recorder)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn received(&self, message: &ServerMsg) {
+        self.write(Event::Received(message.clone()));
+    }
+
+    pub fn sent(&self, message: &ClientMsg) {
+        self.write(Event::Sent(message.clone()));
+    }
+
+    // a recording that can't be written is worth a warning, not the session
+    fn write(&self, event: Event) {
+        let record = Record { at_micros: self.started.elapsed().as_micros() as u64, event: Some(event) };
+        if let Err(e) = self.file.lock().unwrap().write_all(&record.encode_length_delimited_to_vec()) {
+            warn!("cannot write to the recording {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Err(e) = self.file.lock().unwrap().flush() {
+            warn!("cannot write to the recording {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+//None for recordings made before there were headers
+pub fn header(records: &[Record]) -> Option<&RecordingHeader> {
+    match records.first()?.event.as_ref()? {
+        Event::Header(header) => Some(header),
+        _ => None,
+    }
+}
+
+pub fn read(path: &Path) -> io::Result<Vec<Record>> {
+    let bytes = fs::read(path)?;
+    let mut buf = bytes.as_slice();
+
+    let mut records = Vec::new();
+    while !buf.is_empty() {
+        let record = Record::decode_length_delimited(&mut buf)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::server_msg::{Data, SimUpdate};
+    use crate::proto::Point;
+
+    #[test]
+    fn a_recording_reads_back_in_order() {
+        let dir = std::env::temp_dir().join(format!("recording-test-{}", std::process::id()));
+        let update = ServerMsg { data: Some(Data::Update(SimUpdate { l: Some(Point { x: -1.5 }) })) };
+
+        let header = RecordingHeader { controller: "bang-bang".to_string(), tick_ms: 50 };
+        let recorder = Recorder::create(&dir, header.clone()).unwrap();
+        recorder.received(&update);
+        recorder.sent(&ClientMsg { c: -7 });
+        let path = recorder.path().to_path_buf();
+        drop(recorder);
+
+        let records = read(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(super::header(&records), Some(&header));
+        assert_eq!(records[1].event, Some(Event::Received(update)));
+        assert_eq!(records[2].event, Some(Event::Sent(ClientMsg { c: -7 })));
+        assert!(records[1].at_micros <= records[2].at_micros);
+    }
+
+    #[test]
+    fn recordings_started_together_get_files_of_their_own() {
+        let dir = std::env::temp_dir().join(format!("recording-test-{}-together", std::process::id()));
+        let header = RecordingHeader { controller: "p".to_string(), tick_ms: 20 };
+
+        let recorders: Vec<Recorder> = (0..8).map(|_| Recorder::create(&dir, header.clone()).unwrap()).collect();
+        for (c, recorder) in recorders.iter().enumerate() {
+            recorder.sent(&ClientMsg { c: c as i32 });
+        }
+        let paths: Vec<PathBuf> = recorders.iter().map(|recorder| recorder.path().to_path_buf()).collect();
+        drop(recorders);
+
+        for (c, path) in paths.iter().enumerate() {
+            assert_eq!(read(path).unwrap()[1].event, Some(Event::Sent(ClientMsg { c: c as i32 })));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_cut_off_recording_is_an_error() {
+        let path = std::env::temp_dir().join(format!("recording-test-{}-cut.rec", std::process::id()));
+        let record = Record { at_micros: 5, event: Some(Event::Sent(ClientMsg { c: 1 })) };
+        let bytes = record.encode_length_delimited_to_vec();
+        fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+
+        let error = read(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tonic::{Request, Response, Status};
+
+use seank_tonic_project_rs::proto::admin_server::Admin;
+use seank_tonic_project_rs::proto::{ListSessionsRequest, ListSessionsResponse, SessionInfo};
+
+// every Connection the mock server is running, each with an id, so service.Admin can list them. a Connection takes
+// a Slot before it streams anything and gives it back by dropping it, however the stream ends (SimOver, an
+// abort, the client going away), at which point the session moves to the short list of recent ones.
+
+const RECENT: usize = 16; //how many ended sessions are still listed
+
+#[derive(Debug)]
+struct Entry {
+    scenario: String,
+    peer: String,
+    started: Instant,
+    ended: Option<Duration>, //how long it ran, once it has ended
+    position: f32,
+    ticks: u64,
+    outcome: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Sessions {
+    next_id: u64,
+    active: BTreeMap<u64, Entry>,
+    recent: VecDeque<(u64, Entry)>, //newest first
+}
+
+#[derive(Debug)]
+pub struct Registry {
+    limit: usize,
+    sessions: Mutex<Sessions>,
+}
+
+impl Registry {
+    pub fn new(limit: usize) -> Arc<Registry> {
+        Arc::new(Registry { limit, sessions: Mutex::default() })
+    }
+
+    fn sessions(&self) -> MutexGuard<'_, Sessions> {
+        self.sessions.lock().unwrap() //only poisoned if a panic happened while holding it, and nothing in here panics
+    }
+
+    //a place for one more session, or why not when `limit` are running already
+    pub fn open(self: &Arc<Self>, scenario: &str, peer: Option<SocketAddr>) -> Result<Slot, String> {
+        let mut sessions = self.sessions();
+        if sessions.active.len() >= self.limit {
+            return Err(format!("the server is at its limit of {} sessions", self.limit));
+        }
+
+        sessions.next_id += 1;
+        let id = sessions.next_id;
+        sessions.active.insert(id, Entry {
+            scenario: scenario.to_string(),
+            peer: peer.map_or_else(|| "unknown".to_string(), |peer| peer.to_string()),
+            started: Instant::now(),
+            ended: None,
+            position: 0.0,
+            ticks: 0,
+            outcome: None,
+        });
+        Ok(Slot { registry: self.clone(), id })
+    }
+
+    //the running sessions by id, then the ones that ended lately, newest first
+    pub fn list(&self) -> Vec<SessionInfo> {
+        let sessions = self.sessions();
+        let info = |id: u64, entry: &Entry| SessionInfo {
+            id,
+            scenario: entry.scenario.clone(),
+            peer: entry.peer.clone(),
+            position: entry.position,
+            ticks: entry.ticks,
+            outcome: entry.outcome.clone().unwrap_or_else(|| "running".to_string()),
+            elapsed_ms: entry.ended.unwrap_or_else(|| entry.started.elapsed()).as_millis() as u64,
+            active: entry.ended.is_none(),
+        };
+
+        sessions.active.iter().map(|(id, entry)| info(*id, entry))
+            .chain(sessions.recent.iter().map(|(id, entry)| info(*id, entry)))
+            .collect()
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+}
+
+// one running session's place in the registry
+#[derive(Debug)]
+pub struct Slot {
+    registry: Arc<Registry>,
+    id: u64,
+}
+
+impl Slot {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn with_entry(&self, change: impl FnOnce(&mut Entry)) {
+        if let Some(entry) = self.registry.sessions().active.get_mut(&self.id) {
+            change(entry);
+        }
+    }
+
+    pub fn update(&self, position: f32, ticks: u64) {
+        self.with_entry(|entry| {
+            entry.position = position;
+            entry.ticks = ticks;
+        });
+    }
+
+    pub fn finish(&self, outcome: &str) {
+        self.with_entry(|entry| entry.outcome = Some(outcome.to_string()));
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut sessions = self.registry.sessions();
+        if let Some(mut entry) = sessions.active.remove(&self.id) {
+            entry.ended = Some(entry.started.elapsed());
+            entry.outcome.get_or_insert_with(|| "ended early".to_string());
+            sessions.recent.push_front((self.id, entry));
+            sessions.recent.truncate(RECENT);
+        }
+    }
+}
+
+// service.Admin, served next to service.Controller
+#[derive(Debug)]
+pub struct AdminService {
+    pub registry: Arc<Registry>,
+}
+
+#[tonic::async_trait]
+impl Admin for AdminService {
+    async fn list_sessions(&self, _: Request<ListSessionsRequest>) -> Result<Response<ListSessionsResponse>, Status> {
+        Ok(Response::new(ListSessionsResponse {
+            sessions: self.registry.list(),
+            limit: self.registry.limit() as u32,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sessions_get_their_own_ids_and_state() {
+        let registry = Registry::new(4);
+        let first = registry.open("default", None).unwrap();
+        let second = registry.open("noisy", "[::1]:4000".parse().ok()).unwrap();
+
+        first.update(12.5, 40);
+        second.update(-3.0, 7);
+
+        let sessions = registry.list();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!((sessions[0].id, sessions[0].scenario.as_str(), sessions[0].position, sessions[0].ticks), (first.id(), "default", 12.5, 40));
+        assert_eq!((sessions[1].id, sessions[1].peer.as_str(), sessions[1].position, sessions[1].ticks), (second.id(), "[::1]:4000", -3.0, 7));
+        assert!(sessions.iter().all(|session| session.active && session.outcome == "running"));
+        assert_ne!(first.id(), second.id());
+    }
+
+    #[test]
+    fn no_more_than_the_limit_run_at_once() {
+        let registry = Registry::new(2);
+        let first = registry.open("default", None).unwrap();
+        let _second = registry.open("default", None).unwrap();
+
+        assert!(registry.open("default", None).unwrap_err().contains("limit of 2 sessions"));
+
+        drop(first);
+        assert!(registry.open("default", None).is_ok());
+    }
+
+    #[test]
+    fn ended_sessions_keep_their_outcome_for_a_while() {
+        let registry = Registry::new(RECENT + 1);
+        let reached = registry.open("default", None).unwrap();
+        reached.finish("settled inside the goal area");
+        drop(reached);
+        drop(registry.open("default", None).unwrap());
+
+        let sessions = registry.list();
+        assert_eq!(sessions[0].outcome, "ended early"); //newest first
+        assert_eq!(sessions[1].outcome, "settled inside the goal area");
+        assert!(sessions.iter().all(|session| !session.active));
+
+        for _ in 0..RECENT {
+            drop(registry.open("default", None).unwrap());
+        }
+        assert_eq!(registry.list().len(), RECENT);
+    }
+}
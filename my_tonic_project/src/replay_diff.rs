@@ -0,0 +1,214 @@
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+use clap::Parser;
+
+use seank_tonic_project_rs::{control, recording};
+use seank_tonic_project_rs::control::{Controller, ServiceInstruction, ServiceState};
+use seank_tonic_project_rs::proto::record::Event;
+use seank_tonic_project_rs::proto::{ClientMsg, Record};
+use seank_tonic_project_rs::session::{Session, Step};
+
+// replay_diff RECORDING [--controller NAME]
+//
+// feeds the server messages of a recording (see recording.rs) through a controller, exactly as the client's
+// outbound stream does, and compares each control value with the one the client actually sent. handy for
+// checking that a controller change only changes what it should, or that a bug is reproducible offline.
+
+#[derive(Parser, Debug)]
+#[command(name = "replay_diff", about = "Re-runs a controller against a recorded session and diffs its control values")]
+struct Cli {
+    /// A recording made by the client with --record
+    recording: PathBuf,
+
+    /// p, pid or bang-bang, instead of the controller the session was recorded with
+    #[arg(long)]
+    controller: Option<String>,
+
+    /// A tick in milliseconds, instead of the one the session was recorded with
+    #[arg(long)]
+    tick_ms: Option<u64>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Difference {
+    index: usize,
+    at: Duration, //when the recorded value was sent
+    x: Option<f32>, //the position it was worked out from
+    recorded: i32,
+    replayed: i32,
+}
+
+#[derive(Debug, Default)]
+struct Report {
+    compared: usize,
+    differences: Vec<Difference>,
+    recorded: usize,
+    replayed: usize,
+}
+
+//the control values `controller` gives for the recorded server messages, with the position each one answers
+fn replay(records: &[Record], controller: &mut dyn Controller, dt: f32) -> Vec<(Option<f32>, i32)> {
+    let mut session = Session::default();
+    let mut instruction: Option<ServiceInstruction> = None;
+    let mut prev_state: Option<ServiceState> = None;
+    let mut outputs = Vec::new();
+
+    for record in records {
+        let Some(Event::Received(message)) = &record.event else { continue };
+        let state = match session.on_message(message) {
+            Ok(Step::Started(started, state)) => {
+                instruction = Some(started);
+                Some(state)
+            }
+            Ok(Step::Moved(state)) => Some(state),
+            Ok(Step::Unknown) => None,
+            Ok(Step::Over { .. }) | Err(_) => break, //the client stopped sending here too
+        };
+
+        // the same choices the client's outbound stream makes
+        let output = match (&state, &instruction) {
+            (Some(state), Some(instruction)) => {
+                let prev = prev_state.as_ref().unwrap_or(state);
+                controller.control(state, prev, instruction, dt)
+            }
+            _ => 0,
+        };
+        let x = state.as_ref().map(|state| state.x);
+        if state.is_some() {
+            prev_state = state;
+        }
+        outputs.push((x, output));
+    }
+    outputs
+}
+
+fn diff(records: &[Record], controller: &mut dyn Controller, dt: f32) -> Report {
+    let sent: Vec<(Duration, i32)> = records.iter()
+        .filter_map(|record| match record.event {
+            Some(Event::Sent(ClientMsg { c })) => Some((Duration::from_micros(record.at_micros), c)),
+            _ => None,
+        })
+        .collect();
+    let replayed = replay(records, controller, dt);
+
+    let mut report = Report { recorded: sent.len(), replayed: replayed.len(), ..Report::default() };
+    for (index, ((at, recorded), (x, replayed))) in sent.iter().zip(replayed).enumerate() {
+        report.compared += 1;
+        if *recorded != replayed {
+            report.differences.push(Difference { index, at: *at, x, recorded: *recorded, replayed });
+        }
+    }
+    report
+}
+
+//the controller and tick to replay with. the recording says how it was made, the options are there to try
+//something else (or for recordings made before they had a header)
+fn settings(records: &[Record], controller: Option<String>, tick_ms: Option<u64>) -> Result<(String, u64), &'static str> {
+    let header = recording::header(records);
+    let controller = controller.or_else(|| header.map(|header| header.controller.clone()))
+        .ok_or("the recording doesn't say which controller made it, pass --controller")?;
+    let tick_ms = tick_ms.or_else(|| header.map(|header| header.tick_ms))
+        .ok_or("the recording doesn't say which tick it was made with, pass --tick-ms")?;
+    Ok((controller, tick_ms))
+}
+
+fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    let records = recording::read(&cli.recording)?;
+    let (name, tick_ms) = settings(&records, cli.controller, cli.tick_ms)?;
+    let mut controller = control::from_name(&name)?;
+
+    let report = diff(&records, controller.as_mut(), Duration::from_millis(tick_ms).as_secs_f32());
+
+    for difference in &report.differences {
+        let x = difference.x.map_or("-".to_string(), |x| x.to_string());
+        println!("#{} at {:.3}s, x = {}: recorded {}, {} gives {}",
+                 difference.index, difference.at.as_secs_f32(), x, difference.recorded, name, difference.replayed);
+    }
+    // the client can stop sending a value or two early when SimOver arrives, more than that is worth a look
+    if report.recorded > report.replayed {
+        println!("the recording has {} control values but only {} server messages to answer", report.recorded, report.replayed);
+    }
+    println!("{} of {} control values differ", report.differences.len(), report.compared);
+
+    let same = report.differences.is_empty() && report.recorded <= report.replayed;
+    Ok(if same { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use seank_tonic_project_rs::control::Proportional;
+    use seank_tonic_project_rs::proto::server_msg::{Data, SimOver, SimStart, SimUpdate};
+    use seank_tonic_project_rs::proto::{Area, Point, RecordingHeader, ServerMsg};
+
+    const DT: f32 = 0.02;
+
+    fn received(data: Data) -> Record {
+        Record { at_micros: 0, event: Some(Event::Received(ServerMsg { data: Some(data) })) }
+    }
+
+    fn sent(c: i32) -> Record {
+        Record { at_micros: 0, event: Some(Event::Sent(ClientMsg { c })) }
+    }
+
+    fn update(x: f32) -> Record {
+        received(Data::Update(SimUpdate { l: Some(Point { x }) }))
+    }
+
+    // a session that went 0 -> -10 -> -20 and ended, the client running a proportional controller
+    fn recording(answers: [i32; 3]) -> Vec<Record> {
+        let start = SimStart {
+            l: Some(Point { x: 0.0 }),
+            limit: Some(Area { n_point: Some(Point { x: -50.0 }), m_point: Some(Point { x: 50.0 }) }),
+            g: Some(Area { n_point: Some(Point { x: -26.0 }), m_point: Some(Point { x: -24.0 }) }),
+        };
+        vec![
+            received(Data::Start(start)), sent(answers[0]),
+            update(-10.0), sent(answers[1]),
+            update(-20.0), sent(answers[2]),
+            received(Data::Ended(SimOver { success: true, details: None })),
+        ]
+    }
+
+    #[test]
+    fn the_same_controller_gives_the_same_values() {
+        let report = diff(&recording([-25, -15, -5]), &mut Proportional::new(1.0, 100.0), DT);
+
+        assert_eq!(report.compared, 3);
+        assert!(report.differences.is_empty(), "{:?}", report.differences);
+    }
+
+    #[test]
+    fn a_different_controller_shows_where_it_differs() {
+        let report = diff(&recording([-25, -15, -5]), &mut Proportional::new(2.0, 100.0), DT);
+
+        let indices: Vec<usize> = report.differences.iter().map(|d| d.index).collect();
+        assert_eq!(indices, [0, 1, 2]);
+        assert_eq!(report.differences[1].x, Some(-10.0));
+        assert_eq!((report.differences[1].recorded, report.differences[1].replayed), (-15, -30));
+    }
+
+    #[test]
+    fn messages_after_the_end_are_not_answered() {
+        let mut records = recording([-25, -15, -5]);
+        records.push(update(-30.0));
+
+        assert_eq!(diff(&records, &mut Proportional::new(1.0, 100.0), DT).replayed, 3);
+    }
+
+    #[test]
+    fn the_header_decides_unless_overridden() {
+        let header = RecordingHeader { controller: "bang-bang".to_string(), tick_ms: 50 };
+        let mut records = recording([-25, -15, -5]);
+        records.insert(0, Record { at_micros: 0, event: Some(Event::Header(header)) });
+
+        assert_eq!(settings(&records, None, None), Ok(("bang-bang".to_string(), 50)));
+        assert_eq!(settings(&records, Some("p".to_string()), Some(10)), Ok(("p".to_string(), 10)));
+
+        let headless = recording([-25, -15, -5]);
+        assert!(settings(&headless, None, Some(20)).unwrap_err().contains("--controller"));
+        assert!(settings(&headless, Some("p".to_string()), None).unwrap_err().contains("--tick-ms"));
+    }
+}
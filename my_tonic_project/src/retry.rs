@@ -0,0 +1,208 @@
+use rand::Rng;
+use std::error::Error;
+use std::fmt;
+use std::io::ErrorKind;
+use std::time::Duration;
+use tonic::{Code, Status};
+
+use seank_tonic_project_rs::match_for_io_error;
+use seank_tonic_project_rs::session::SessionError;
+
+// what the client does when a session goes wrong. errors are sorted by what caused them:
+//
+//   unavailable      the server can't be reached, says it is unavailable       retried
+//                    or is too busy for another session (resource exhausted)
+//   stream reset     the connection or the http/2 stream broke mid session      retried
+//   protocol         the server sent something that makes no sense (session.rs) retried
+//   fatal            the server refused us (bad token, no such rpc or          not retried, the client exits
+//                    scenario, ...)
+//                    or a recording was asked for and can't be written
+//   other            anything else                                              retried
+//
+// retries wait initial, 2 x initial, 4 x initial ... up to max, each wait picked at random from its upper half
+// so that a fleet of clients dropped at the same moment doesn't come back at the same moment either.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    Unavailable,
+    StreamReset,
+    Protocol,
+    Fatal,
+    Other,
+}
+
+impl fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ErrorClass::Unavailable => "unavailable",
+            ErrorClass::StreamReset => "stream reset",
+            ErrorClass::Protocol => "protocol",
+            ErrorClass::Fatal => "fatal",
+            ErrorClass::Other => "other",
+        };
+        f.write_str(name)
+    }
+}
+
+fn classify_code(code: Code) -> Option<ErrorClass> {
+    match code {
+        Code::Unauthenticated | Code::PermissionDenied | Code::Unimplemented | Code::InvalidArgument | Code::NotFound => Some(ErrorClass::Fatal),
+        Code::Unavailable | Code::ResourceExhausted => Some(ErrorClass::Unavailable),
+        _ => None, //a status wrapping a broken connection only says Unknown or Internal, look underneath
+    }
+}
+
+pub fn classify(error: &(dyn Error + 'static)) -> ErrorClass {
+    match error.downcast_ref::<SessionError>() {
+        Some(session_error) if session_error.is_protocol() => return ErrorClass::Protocol,
+        Some(SessionError::ClosedEarly | SessionError::Stalled(_)) => return ErrorClass::StreamReset,
+        Some(SessionError::NotServing(_)) => return ErrorClass::Unavailable,
+        Some(SessionError::Unauthenticated(_) | SessionError::Record(_) | SessionError::Controller(_)) => return ErrorClass::Fatal,
+        Some(session_error) => return session_error.source().map_or(ErrorClass::Other, classify),
+        None => {}
+    }
+    if let Some(class) = error.downcast_ref::<Status>().and_then(|status| classify_code(status.code())) {
+        return class;
+    }
+
+    if let Some(io_err) = match_for_io_error(error) {
+        return match io_err.kind() {
+            ErrorKind::ConnectionRefused | ErrorKind::NotConnected | ErrorKind::TimedOut => ErrorClass::Unavailable,
+            ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe | ErrorKind::UnexpectedEof => ErrorClass::StreamReset,
+            _ => ErrorClass::Other,
+        };
+    }
+
+    let mut err = error;
+    loop {
+        if err.downcast_ref::<h2::Error>().is_some_and(|h2_err| h2_err.is_reset() || h2_err.is_go_away()) {
+            return ErrorClass::StreamReset;
+        }
+        // connecting failed before any stream existed
+        if err.is::<tonic::transport::Error>() {
+            return ErrorClass::Unavailable;
+        }
+        match err.source() {
+            Some(source) => err = source,
+            None => return ErrorClass::Other,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    failures: u32,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Backoff {
+        Backoff { initial, max, failures: 0 }
+    }
+
+    //how long to wait before the next attempt, each call counts as one more failure in a row
+    pub fn next_delay(&mut self) -> Duration {
+        let ceiling = self.ceiling();
+        self.failures = self.failures.saturating_add(1);
+        let half = ceiling / 2;
+        half + rand::thread_rng().gen_range(Duration::ZERO..=ceiling - half)
+    }
+
+    //after a session that went fine, the next failure starts from `initial` again
+    pub fn reset(&mut self) {
+        self.failures = 0;
+    }
+
+    fn ceiling(&self) -> Duration {
+        let factor = 2u32.saturating_pow(self.failures.min(31));
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_double_up_to_the_cap() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(1000));
+
+        let ceilings = [100, 200, 400, 800, 1000, 1000];
+        for ceiling in ceilings {
+            let delay = backoff.next_delay();
+            assert!(delay <= Duration::from_millis(ceiling), "{:?} above {}ms", delay, ceiling);
+            assert!(delay >= Duration::from_millis(ceiling / 2), "{:?} below half of {}ms", delay, ceiling);
+        }
+    }
+
+    #[test]
+    fn a_long_run_of_failures_does_not_overflow() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(60));
+
+        for _ in 0..100 {
+            assert!(backoff.next_delay() <= Duration::from_secs(60));
+        }
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(10));
+        for _ in 0..5 {
+            backoff.next_delay();
+        }
+
+        backoff.reset();
+
+        assert!(backoff.next_delay() <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn refusals_are_fatal() {
+        assert_eq!(classify(&Status::unauthenticated("bad token")), ErrorClass::Fatal);
+        assert_eq!(classify(&Status::permission_denied("not you")), ErrorClass::Fatal);
+        assert_eq!(classify(&Status::unavailable("restarting")), ErrorClass::Unavailable);
+    }
+
+    #[test]
+    fn an_unknown_scenario_is_fatal() {
+        let status = Status::not_found("no scenario called \"nosiy\", there is default, noisy");
+        assert_eq!(classify(&SessionError::from(status)), ErrorClass::Fatal);
+    }
+
+    #[test]
+    fn a_full_server_is_waited_out() {
+        let status = Status::resource_exhausted("the server is at its limit of 64 sessions");
+        assert_eq!(classify(&SessionError::from(status)), ErrorClass::Unavailable);
+    }
+
+    #[test]
+    fn broken_connections_are_stream_resets() {
+        let reset = std::io::Error::new(ErrorKind::ConnectionReset, "reset by peer");
+        assert_eq!(classify(&reset), ErrorClass::StreamReset);
+
+        let status = Status::from_error(Box::new(std::io::Error::new(ErrorKind::BrokenPipe, "broken pipe")));
+        assert_eq!(classify(&status), ErrorClass::StreamReset);
+
+        let refused = std::io::Error::new(ErrorKind::ConnectionRefused, "refused");
+        assert_eq!(classify(&refused), ErrorClass::Unavailable);
+    }
+
+    #[test]
+    fn session_errors_are_classified_by_what_caused_them() {
+        assert_eq!(classify(&SessionError::DuplicateStart), ErrorClass::Protocol);
+        assert_eq!(classify(&SessionError::IncompleteStart { missing: vec!["l"] }), ErrorClass::Protocol);
+        assert_eq!(classify(&SessionError::ClosedEarly), ErrorClass::StreamReset);
+        assert_eq!(classify(&SessionError::Stalled(Duration::from_secs(5))), ErrorClass::StreamReset);
+        assert_eq!(classify(&SessionError::NotServing("NOT_SERVING")), ErrorClass::Unavailable);
+        assert_eq!(classify(&SessionError::from(Status::unauthenticated("bad token"))), ErrorClass::Fatal);
+        assert_eq!(classify(&SessionError::Controller("unknown controller".to_string())), ErrorClass::Fatal);
+    }
+
+    #[test]
+    fn anything_else_is_other() {
+        let error: Box<dyn Error> = "the server closed the stream".into();
+        assert_eq!(classify(error.as_ref()), ErrorClass::Other);
+        assert_eq!(classify(&Status::internal("oops")), ErrorClass::Other);
+    }
+}
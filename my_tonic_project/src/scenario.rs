@@ -0,0 +1,299 @@
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+// what a simulation starts from and how its world behaves. scenario files are TOML (or JSON when the file ends
+// in .json) holding one or more [[scenario]] tables, any field left out keeps the value of Scenario::default():
+//
+//   [[scenario]]
+//   name = "far-goal"
+//   start = 0.0
+//   limit = { min = -100.0, max = 100.0 }
+//   goal = { min = 70.0, max = 74.0 }
+//   tick_ms = 20
+//   max_duration_ms = 90000
+//   dynamics = { mass = 2.0, drag = 0.8, max_control = 100.0, settle_speed = 0.5, settle_ms = 500 }
+//   noise = { position = 0.1, force = 1.0 }
+//   seed = 7
+//
+// see scenarios/ for complete files.
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Range {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Range {
+    pub fn contains(&self, x: f32) -> bool {
+        self.min <= x && x <= self.max
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Dynamics {
+    pub mass: f32,
+    pub drag: f32,
+    pub max_control: f32,
+    pub settle_speed: f32,
+    #[serde(rename = "settle_ms", with = "millis")]
+    pub settle_time: Duration,
+}
+
+// random disturbances, both uniform in -amount..=amount and both off by default
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Noise {
+    pub position: f32, //added to every position the client is told, the true position is not affected
+    pub force: f32,    //added to the client's control every tick
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scenario {
+    pub name: String,
+    pub start: f32,
+    pub limit: Range,
+    pub goal: Range,
+    #[serde(rename = "tick_ms", with = "millis")]
+    pub tick: Duration,
+    #[serde(rename = "max_duration_ms", with = "millis")]
+    pub max_duration: Duration,
+    pub dynamics: Dynamics,
+    pub noise: Noise,
+    pub seed: Option<u64>, //makes the noise the same every run
+}
+
+impl Default for Dynamics {
+    fn default() -> Self {
+        Dynamics {
+            mass: 1.0,
+            drag: 1.0,
+            max_control: 100.0,
+            settle_speed: 0.5,
+            settle_time: Duration::from_millis(500),
+        }
+    }
+}
+
+// the numbers the mock server always used to send, start 0.0, limits ±50 and goal -26..-24
+impl Default for Scenario {
+    fn default() -> Self {
+        Scenario {
+            name: "default".to_string(),
+            start: 0.0,
+            limit: Range { min: -50.0, max: 50.0 },
+            goal: Range { min: -26.0, max: -24.0 },
+            tick: Duration::from_millis(20),
+            max_duration: Duration::from_secs(60),
+            dynamics: Dynamics::default(),
+            noise: Noise::default(),
+            seed: None,
+        }
+    }
+}
+
+mod millis {
+    use serde::{Deserialize, Deserializer};
+    use std::time::Duration;
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_millis)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScenarioFile {
+    scenario: Vec<Scenario>,
+}
+
+fn check_range(what: &str, range: &Range) -> Result<(), String> {
+    if !range.min.is_finite() || !range.max.is_finite() || range.min >= range.max {
+        return Err(format!("{} must have a finite min below its max, got {}..{}", what, range.min, range.max));
+    }
+    Ok(())
+}
+
+impl Scenario {
+    pub fn validate(&self) -> Result<(), String> {
+        let fail = |problem: String| Err(format!("scenario {:?}: {}", self.name, problem));
+
+        if self.name.trim().is_empty() {
+            return Err("every scenario needs a name".to_string());
+        }
+        if let Err(problem) = check_range("limit", &self.limit).and(check_range("goal", &self.goal)) {
+            return fail(problem);
+        }
+        if !self.limit.contains(self.start) {
+            return fail(format!("start {} is outside the limit area", self.start));
+        }
+        if !self.limit.contains(self.goal.min) || !self.limit.contains(self.goal.max) {
+            return fail("the goal area must be inside the limit area".to_string());
+        }
+        if self.tick.is_zero() {
+            return fail("tick_ms must be at least 1".to_string());
+        }
+        if self.max_duration < self.tick {
+            return fail("max_duration_ms must be at least one tick".to_string());
+        }
+
+        let Dynamics { mass, drag, max_control, settle_speed, .. } = self.dynamics;
+        if !(mass > 0.0 && mass.is_finite()) {
+            return fail(format!("dynamics.mass must be above 0, got {}", mass));
+        }
+        for (field, value) in [("dynamics.drag", drag), ("dynamics.max_control", max_control),
+            ("dynamics.settle_speed", settle_speed), ("noise.position", self.noise.position), ("noise.force", self.noise.force)] {
+            if !(value >= 0.0 && value.is_finite()) {
+                return fail(format!("{} cannot be negative, got {}", field, value));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn parse(text: &str, json: bool) -> Result<Vec<Scenario>, String> {
+    let file: ScenarioFile = if json {
+        serde_json::from_str(text).map_err(|e| e.to_string())?
+    } else {
+        toml::from_str(text).map_err(|e| e.to_string())?
+    };
+    Ok(file.scenario)
+}
+
+// every scenario in `paths`, checked, names have to be unique across all the files
+pub fn load<P: AsRef<Path>>(paths: &[P]) -> Result<Vec<Scenario>, String> {
+    let mut scenarios = Vec::new();
+    let mut names = HashSet::new();
+
+    for path in paths {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        let json = path.extension().is_some_and(|ext| ext == "json");
+
+        for scenario in parse(&text, json).map_err(|e| format!("{}: {}", path.display(), e))? {
+            scenario.validate().map_err(|e| format!("{}: {}", path.display(), e))?;
+            if !names.insert(scenario.name.clone()) {
+                return Err(format!("{}: scenario {:?} is defined twice", path.display(), scenario.name));
+            }
+            scenarios.push(scenario);
+        }
+    }
+    if scenarios.is_empty() {
+        return Err("the scenario files do not define any scenario".to_string());
+    }
+    Ok(scenarios)
+}
+
+// hands out scenarios to connections, the one they ask for by name or else the next one round-robin
+#[derive(Debug)]
+pub struct Scenarios {
+    scenarios: Vec<Scenario>,
+    next: AtomicUsize,
+}
+
+impl Default for Scenarios {
+    fn default() -> Self {
+        Scenarios::new(vec![Scenario::default()])
+    }
+}
+
+impl Scenarios {
+    pub fn new(scenarios: Vec<Scenario>) -> Scenarios {
+        assert!(!scenarios.is_empty(), "there has to be at least one scenario");
+        Scenarios { scenarios, next: AtomicUsize::new(0) }
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.scenarios.iter().map(|scenario| scenario.name.as_str()).collect()
+    }
+
+    pub fn pick(&self, name: Option<&str>) -> Result<Scenario, String> {
+        match name {
+            Some(name) => self.scenarios.iter()
+                .find(|scenario| scenario.name == name)
+                .cloned()
+                .ok_or_else(|| format!("no scenario called {:?}, there is {}", name, self.names().join(", "))),
+            None => {
+                let next = self.next.fetch_add(1, Ordering::Relaxed);
+                Ok(self.scenarios[next % self.scenarios.len()].clone())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_fields_keep_their_defaults() {
+        let scenarios = parse("[[scenario]]\nname = \"near\"\ngoal = { min = 4.0, max = 6.0 }\ndynamics = { mass = 2.0 }", false).unwrap();
+
+        assert_eq!(scenarios.len(), 1);
+        assert_eq!(scenarios[0].goal, Range { min: 4.0, max: 6.0 });
+        assert_eq!(scenarios[0].dynamics.mass, 2.0);
+        assert_eq!(scenarios[0].dynamics.drag, 1.0);
+        assert_eq!(scenarios[0].tick, Duration::from_millis(20));
+    }
+
+    #[test]
+    fn json_files_hold_the_same_shape() {
+        let scenarios = parse(r#"{"scenario": [{"name": "a", "tick_ms": 10}, {"name": "b", "seed": 3}]}"#, true).unwrap();
+
+        assert_eq!(scenarios[0].tick, Duration::from_millis(10));
+        assert_eq!(scenarios[1].seed, Some(3));
+    }
+
+    #[test]
+    fn unknown_fields_are_refused() {
+        assert!(parse("[[scenario]]\nname = \"a\"\ngaol = { min = 4.0, max = 6.0 }", false).is_err());
+    }
+
+    #[test]
+    fn unknown_fields_in_a_range_are_refused() {
+        assert!(parse("[[scenario]]\nname = \"a\"\ngoal = { min = 4.0, max = 6.0, mni = -5.0 }", false).is_err());
+    }
+
+    #[test]
+    fn the_goal_has_to_be_inside_the_limits() {
+        let scenario = Scenario { goal: Range { min: 45.0, max: 55.0 }, ..Scenario::default() };
+
+        assert!(scenario.validate().unwrap_err().contains("inside the limit area"));
+    }
+
+    #[test]
+    fn nonsense_dynamics_are_refused() {
+        let mut scenario = Scenario::default();
+        scenario.dynamics.mass = 0.0;
+        assert!(scenario.validate().unwrap_err().contains("dynamics.mass"));
+
+        let mut scenario = Scenario::default();
+        scenario.noise.force = -1.0;
+        assert!(scenario.validate().unwrap_err().contains("noise.force"));
+    }
+
+    #[test]
+    fn the_shipped_scenario_files_are_valid() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios");
+        let mut paths: Vec<_> = std::fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+        paths.sort();
+
+        load(&paths).unwrap();
+    }
+
+    #[test]
+    fn connections_get_scenarios_by_name_or_in_turn() {
+        let named = |name: &str| Scenario { name: name.to_string(), ..Scenario::default() };
+        let scenarios = Scenarios::new(vec![named("a"), named("b")]);
+
+        let turns: Vec<String> = (0..3).map(|_| scenarios.pick(None).unwrap().name).collect();
+        assert_eq!(turns, ["a", "b", "a"]);
+        assert_eq!(scenarios.pick(Some("b")).unwrap().name, "b");
+        assert!(scenarios.pick(Some("c")).unwrap_err().contains("a, b"));
+    }
+}
@@ -0,0 +1,260 @@
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+use tonic::Status;
+
+use crate::control::{ServiceInstruction, ServiceState};
+use crate::proto::server_msg::{Data, SimOver, SimStart, SimUpdate};
+use crate::proto::{Area, ServerMsg};
+
+// what the client makes of each ServerMsg in a session. a session has to open with one complete SimStart
+// (start point, limit area and goal area, each with both points), anything else the server gets wrong ends
+// the session with a SessionError rather than a panic, and the supervisor in client.rs decides what next.
+
+#[derive(Debug)]
+pub enum SessionError {
+    IncompleteStart { missing: Vec<&'static str> },
+    DuplicateStart,
+    UpdateBeforeStart,
+    ClosedEarly, //the server ended the stream without a SimOver
+    Stalled(Duration), //nothing arrived for this long, see stall_timeout_ms
+    NotServing(&'static str), //the health check said so, with the status it gave
+    Unauthenticated(String), //the server turned our token down, with its reason
+    Rpc(Box<Status>), //boxed, a Status is big and the other variants are tiny
+    Connect(tonic::transport::Error),
+    Record(std::io::Error), //--record was given but the recording can't be created
+    Controller(String), //--controller names one that doesn't exist
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::IncompleteStart { missing } => write!(f, "SimStart is missing {}", missing.join(", ")),
+            SessionError::DuplicateStart => write!(f, "a second SimStart arrived in the same session"),
+            SessionError::UpdateBeforeStart => write!(f, "a SimUpdate arrived before the SimStart"),
+            SessionError::ClosedEarly => write!(f, "the server closed the stream before the simulation was over"),
+            SessionError::Stalled(waited) => write!(f, "the server sent nothing for {:?}", waited),
+            SessionError::NotServing(status) => write!(f, "the health check says the controller service is {}", status),
+            SessionError::Unauthenticated(reason) => write!(f, "the server rejected the token: {}", reason),
+            SessionError::Rpc(status) => write!(f, "{}", status),
+            SessionError::Connect(error) => write!(f, "cannot connect: {}", error),
+            SessionError::Record(error) => write!(f, "cannot start a recording: {}", error),
+            SessionError::Controller(problem) => write!(f, "cannot make the controller: {}", problem),
+        }
+    }
+}
+
+impl Error for SessionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SessionError::Rpc(status) => Some(status.as_ref()),
+            SessionError::Connect(error) => Some(error),
+            SessionError::Record(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl SessionError {
+    //the server broke the protocol, as opposed to the connection breaking
+    pub fn is_protocol(&self) -> bool {
+        matches!(self, SessionError::IncompleteStart { .. } | SessionError::DuplicateStart | SessionError::UpdateBeforeStart)
+    }
+}
+
+impl From<Status> for SessionError {
+    fn from(status: Status) -> Self {
+        match status.code() {
+            tonic::Code::Unauthenticated => SessionError::Unauthenticated(status.message().to_string()),
+            _ => SessionError::Rpc(Box::new(status)),
+        }
+    }
+}
+
+impl From<tonic::transport::Error> for SessionError {
+    fn from(error: tonic::transport::Error) -> Self {
+        SessionError::Connect(error)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Step {
+    Started(ServiceInstruction, ServiceState),
+    Moved(ServiceState),
+    Over { success: bool, details: String },
+    Unknown, //an update without a position, or a kind of message this client doesn't know yet
+}
+
+#[derive(Debug, Default)]
+pub struct Session {
+    started: bool,
+}
+
+fn area(area: &Option<Area>, name: &'static str, min: &'static str, max: &'static str, missing: &mut Vec<&'static str>) -> Option<(f32, f32)> {
+    match area {
+        Some(Area { n_point: Some(n), m_point: Some(m) }) => Some((n.x, m.x)),
+        Some(Area { n_point, m_point }) => {
+            if n_point.is_none() {
+                missing.push(min);
+            }
+            if m_point.is_none() {
+                missing.push(max);
+            }
+            None
+        }
+        None => {
+            missing.push(name);
+            None
+        }
+    }
+}
+
+fn start(start: &SimStart) -> Result<(ServiceInstruction, ServiceState), SessionError> {
+    let mut missing = Vec::new();
+    if start.l.is_none() {
+        missing.push("l");
+    }
+    let limit = area(&start.limit, "limit", "limit.n_point", "limit.m_point", &mut missing);
+    let goal = area(&start.g, "g", "g.n_point", "g.m_point", &mut missing);
+
+    match (&start.l, limit, goal) {
+        (Some(l), Some((b_minimum_x, b_maximum_x)), Some((g_minimum_x, g_maximum_x))) => {
+            let instruction = ServiceInstruction { b_minimum_x, b_maximum_x, g_minimum_x, g_maximum_x };
+            Ok((instruction, ServiceState { x: l.x }))
+        }
+        _ => Err(SessionError::IncompleteStart { missing }),
+    }
+}
+
+impl Session {
+    pub fn on_message(&mut self, message: &ServerMsg) -> Result<Step, SessionError> {
+        match &message.data {
+            Some(Data::Start(_)) if self.started => Err(SessionError::DuplicateStart),
+            Some(Data::Start(sim_start)) => {
+                let (instruction, state) = start(sim_start)?;
+                self.started = true;
+                Ok(Step::Started(instruction, state))
+            }
+            Some(Data::Update(_)) if !self.started => Err(SessionError::UpdateBeforeStart),
+            Some(Data::Update(SimUpdate { l: Some(location) })) => Ok(Step::Moved(ServiceState { x: location.x })),
+            Some(Data::Ended(SimOver { success, details })) => {
+                Ok(Step::Over { success: *success, details: details.clone().unwrap_or_default() })
+            }
+            Some(Data::Update(SimUpdate { l: None })) | None => Ok(Step::Unknown),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::Point;
+
+    fn point(x: f32) -> Option<Point> {
+        Some(Point { x })
+    }
+
+    fn sim_start() -> SimStart {
+        SimStart {
+            l: point(0.0),
+            limit: Some(Area { n_point: point(-50.0), m_point: point(50.0) }),
+            g: Some(Area { n_point: point(-26.0), m_point: point(-24.0) }),
+        }
+    }
+
+    fn message(data: Data) -> ServerMsg {
+        ServerMsg { data: Some(data) }
+    }
+
+    fn update(x: f32) -> ServerMsg {
+        message(Data::Update(SimUpdate { l: point(x) }))
+    }
+
+    fn missing(result: Result<Step, SessionError>) -> Vec<&'static str> {
+        match result {
+            Err(SessionError::IncompleteStart { missing }) => missing,
+            other => panic!("expected an incomplete start, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn a_complete_start_gives_the_instruction_and_the_first_state() {
+        let mut session = Session::default();
+
+        let step = session.on_message(&message(Data::Start(sim_start()))).unwrap();
+
+        let instruction = ServiceInstruction { b_minimum_x: -50.0, b_maximum_x: 50.0, g_minimum_x: -26.0, g_maximum_x: -24.0 };
+        assert_eq!(step, Step::Started(instruction, ServiceState { x: 0.0 }));
+        assert_eq!(session.on_message(&update(-1.5)).unwrap(), Step::Moved(ServiceState { x: -1.5 }));
+    }
+
+    #[test]
+    fn a_start_without_a_position_is_an_error() {
+        let start = SimStart { l: None, ..sim_start() };
+
+        assert_eq!(missing(Session::default().on_message(&message(Data::Start(start)))), ["l"]);
+    }
+
+    #[test]
+    fn every_missing_part_of_a_start_is_named() {
+        let start = SimStart {
+            l: None,
+            limit: None,
+            g: Some(Area { n_point: point(-26.0), m_point: None }),
+        };
+
+        let error = Session::default().on_message(&message(Data::Start(start))).unwrap_err();
+
+        assert_eq!(error.to_string(), "SimStart is missing l, limit, g.m_point");
+        assert!(error.is_protocol());
+    }
+
+    #[test]
+    fn a_second_start_is_an_error() {
+        let mut session = Session::default();
+        session.on_message(&message(Data::Start(sim_start()))).unwrap();
+
+        assert!(matches!(session.on_message(&message(Data::Start(sim_start()))), Err(SessionError::DuplicateStart)));
+    }
+
+    #[test]
+    fn an_update_before_the_start_is_an_error() {
+        assert!(matches!(Session::default().on_message(&update(3.0)), Err(SessionError::UpdateBeforeStart)));
+    }
+
+    #[test]
+    fn empty_messages_and_updates_without_a_position_are_skipped() {
+        let mut session = Session::default();
+        session.on_message(&message(Data::Start(sim_start()))).unwrap();
+
+        assert_eq!(session.on_message(&ServerMsg { data: None }).unwrap(), Step::Unknown);
+        assert_eq!(session.on_message(&message(Data::Update(SimUpdate { l: None }))).unwrap(), Step::Unknown);
+    }
+
+    #[test]
+    fn sim_over_ends_the_session_with_its_details() {
+        let mut session = Session::default();
+        session.on_message(&message(Data::Start(sim_start()))).unwrap();
+
+        let over = message(Data::Ended(SimOver { success: false, details: None }));
+
+        assert_eq!(session.on_message(&over).unwrap(), Step::Over { success: false, details: String::new() });
+    }
+
+    #[test]
+    fn rpc_errors_keep_their_status_as_the_source() {
+        let error = SessionError::from(Status::unavailable("restarting"));
+
+        let source = error.source().and_then(|source| source.downcast_ref::<Status>()).unwrap();
+        assert_eq!(source.code(), tonic::Code::Unavailable);
+        assert!(!error.is_protocol());
+    }
+
+    #[test]
+    fn a_rejected_token_is_told_apart_from_other_failures() {
+        let error = SessionError::from(Status::unauthenticated("unknown token"));
+
+        assert!(matches!(&error, SessionError::Unauthenticated(reason) if reason == "unknown token"));
+        assert_eq!(error.to_string(), "the server rejected the token: unknown token");
+    }
+}
@@ -0,0 +1,182 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::Duration;
+
+use crate::scenario::Scenario;
+
+// the mock server's world: a single point on a line that the client pushes around with its control value c.
+// every tick of dt seconds
+//
+//   a  = (clamp(c, -max_control, max_control) + force noise) / mass - drag * v
+//   v += a * dt
+//   x += v * dt
+//
+// the run is won once x has stayed inside the goal area, moving slower than settle_speed, for settle_time,
+// and lost as soon as x leaves the limit area or max_duration passes without that happening.
+// the client only ever sees x through reading(), which carries the scenario's position noise.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Running,
+    Reached,
+    LeftLimit,
+    TimedOut,
+}
+
+impl Outcome {
+    pub fn details(&self) -> &'static str {
+        match self {
+            Outcome::Running => "running",
+            Outcome::Reached => "settled inside the goal area",
+            Outcome::LeftLimit => "left the limit area",
+            Outcome::TimedOut => "did not settle inside the goal area in time",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Simulation {
+    scenario: Scenario,
+    x: f32,
+    v: f32,
+    elapsed: Duration,
+    settled: Duration, //how long x has been resting inside the goal area
+    reading: f32,
+    rng: StdRng,
+}
+
+//uniform in -amount..=amount
+fn jitter(rng: &mut StdRng, amount: f32) -> f32 {
+    if amount > 0.0 { rng.gen_range(-amount..=amount) } else { 0.0 }
+}
+
+impl Simulation {
+    pub fn new(scenario: Scenario) -> Simulation {
+        let x = scenario.start;
+        let rng = match scenario.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Simulation { scenario, x, v: 0.0, elapsed: Duration::ZERO, settled: Duration::ZERO, reading: x, rng }
+    }
+
+    pub fn scenario(&self) -> &Scenario {
+        &self.scenario
+    }
+
+    pub fn position(&self) -> f32 {
+        self.x
+    }
+
+    pub fn velocity(&self) -> f32 {
+        self.v
+    }
+
+    //the position as the client gets told it
+    pub fn reading(&self) -> f32 {
+        self.reading
+    }
+
+    //moves the world on by one tick under control `c`
+    pub fn step(&mut self, c: i32) -> Outcome {
+        let Scenario { limit, goal, tick, max_duration, dynamics, noise, .. } = &self.scenario;
+        let dt = tick.as_secs_f32();
+
+        let force = (c as f32).clamp(-dynamics.max_control, dynamics.max_control) + jitter(&mut self.rng, noise.force);
+        let a = force / dynamics.mass - dynamics.drag * self.v;
+        self.v += a * dt;
+        self.x += self.v * dt;
+        self.elapsed += *tick;
+        self.reading = self.x + jitter(&mut self.rng, noise.position);
+
+        if !limit.contains(self.x) {
+            return Outcome::LeftLimit;
+        }
+        if goal.contains(self.x) && self.v.abs() <= dynamics.settle_speed {
+            self.settled += *tick;
+        } else {
+            self.settled = Duration::ZERO;
+        }
+
+        if self.settled >= dynamics.settle_time {
+            Outcome::Reached
+        } else if self.elapsed >= *max_duration {
+            Outcome::TimedOut
+        } else {
+            Outcome::Running
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scenario::Noise;
+
+    fn run(scenario: Scenario, controller: impl Fn(f32) -> i32) -> (Outcome, Simulation) {
+        let mut sim = Simulation::new(scenario);
+        loop {
+            let c = controller(sim.position());
+            match sim.step(c) {
+                Outcome::Running => continue,
+                outcome => return (outcome, sim),
+            }
+        }
+    }
+
+    #[test]
+    fn steering_towards_the_goal_centre_settles_in_the_goal() {
+        let (outcome, sim) = run(Scenario::default(), |x| (-25.0 - x) as i32);
+
+        assert_eq!(outcome, Outcome::Reached);
+        assert!(Scenario::default().goal.contains(sim.position()));
+    }
+
+    #[test]
+    fn pushing_one_way_leaves_the_limit_area() {
+        let (outcome, sim) = run(Scenario::default(), |_| 100);
+
+        assert_eq!(outcome, Outcome::LeftLimit);
+        assert!(sim.position() > 50.0);
+    }
+
+    #[test]
+    fn doing_nothing_times_out() {
+        let (outcome, sim) = run(Scenario::default(), |_| 0);
+
+        assert_eq!(outcome, Outcome::TimedOut);
+        assert_eq!(sim.position(), 0.0);
+    }
+
+    #[test]
+    fn seeded_noise_is_the_same_every_run() {
+        let noisy = Scenario { noise: Noise { position: 0.5, force: 5.0 }, seed: Some(7), ..Scenario::default() };
+        let readings = || {
+            let mut sim = Simulation::new(noisy.clone());
+            (0..50).map(|_| { sim.step(10); sim.reading() }).collect::<Vec<_>>()
+        };
+
+        assert_eq!(readings(), readings());
+        assert!(readings().iter().any(|reading| *reading != 0.0));
+    }
+
+    #[test]
+    fn position_noise_only_changes_what_the_client_is_told() {
+        let noisy = Scenario { noise: Noise { position: 0.5, force: 0.0 }, seed: Some(7), ..Scenario::default() };
+        let mut sim = Simulation::new(noisy);
+
+        sim.step(0);
+
+        assert_eq!(sim.position(), 0.0);
+        assert!(sim.reading().abs() <= 0.5);
+    }
+
+    #[test]
+    fn control_is_clamped_to_max_control() {
+        let mut sim = Simulation::new(Scenario::default());
+
+        sim.step(1_000_000);
+
+        assert!((sim.velocity() - 100.0 * 0.02).abs() < 1e-4);
+    }
+}